@@ -0,0 +1,257 @@
+//! Camera controls for looking around the world.
+
+use crate::{ecs::SimEntityPosRot, gui::SelectedSmitty, simworld::SimWorld};
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+    render::camera::ScalingMode,
+    transform::TransformSystem,
+};
+use bevy_egui::EguiContext;
+
+/// The closest the camera may zoom in, as a projection scale.
+const CAMERA_MIN_SCALE: f32 = 0.05;
+/// The farthest the camera may zoom out, as a projection scale.
+const CAMERA_MAX_SCALE: f32 = 4.0;
+/// How much each line scrolled on the mouse wheel zooms the camera.
+const CAMERA_SCROLL_ZOOM: f32 = 1.1;
+/// How much the zoom keys zoom the camera per second.
+const CAMERA_KEY_ZOOM: f32 = 3.0;
+/// How many scrolled pixels count as one line, for touchpads.
+const CAMERA_PIXELS_PER_LINE: f32 = 20.0;
+/// How fast (in screens per second) the movement keys pan the camera.
+const CAMERA_KEY_PAN_SPEED: f32 = 0.75;
+
+/// Plugin to spawn the camera and let the user move it around.
+pub struct CameraControlPlugin;
+
+impl Plugin for CameraControlPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // Add the camera controls and the event to reset the camera
+            .init_resource::<CameraControls>()
+            .add_event::<FitCameraEvent>()
+            // Spawn the camera fit to the world
+            .add_startup_system(init_camera_system)
+            // Move the camera when requested
+            .add_system(fit_camera_system)
+            .add_system(camera_keyboard_system.before(fit_camera_system))
+            .add_system(camera_pan_system.before(fit_camera_system))
+            .add_system(camera_zoom_system.before(fit_camera_system))
+            // Follow the Smitty once it has moved for the frame
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                camera_follow_system.before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+/// Marker for the camera looking at the world.
+#[derive(Component)]
+pub struct MainCameraMarker;
+
+/// Resource containing the user's camera settings.
+#[derive(Default, Resource)]
+pub struct CameraControls {
+    /// Whether the camera should keep the selected Smitty centered.
+    pub follow: bool,
+    /// The world position that was under the cursor when a drag started, or
+    /// `None` if the camera isn't being dragged.
+    drag_anchor: Option<Vec2>,
+}
+
+/// Event sent to move the camera back to showing the whole world.
+pub struct FitCameraEvent;
+
+/// System to spawn the camera showing the whole world.
+fn init_camera_system(mut commands: Commands, mut fit: EventWriter<FitCameraEvent>) {
+    commands.spawn((Camera2dBundle::default(), MainCameraMarker));
+    fit.send(FitCameraEvent);
+}
+
+/// System to move and zoom the camera to fit the whole world when requested.
+fn fit_camera_system(
+    mut events: EventReader<FitCameraEvent>,
+    simworld: Res<SimWorld>,
+    mut camera: Query<(&mut Transform, &mut OrthographicProjection), With<MainCameraMarker>>,
+) {
+    if events.iter().last().is_none() {
+        return;
+    }
+
+    let (mut transform, mut projection) = camera.single_mut();
+    let size = simworld.size();
+    transform.translation = Vec3::new(size.0 as f32 * 0.5, size.1 as f32 * 0.5, 900.0);
+    projection.scaling_mode = ScalingMode::Auto {
+        min_width: size.0 as f32,
+        min_height: size.1 as f32,
+    };
+    projection.scale = 1.0;
+}
+
+/// System to pan the camera with WASD or the arrow keys, zoom with Q and E (or
+/// minus and equals), toggle following with F, and fit the world with Home.
+fn camera_keyboard_system(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mut controls: ResMut<CameraControls>,
+    mut fit: EventWriter<FitCameraEvent>,
+    mut camera: Query<(&mut Transform, &mut OrthographicProjection), With<MainCameraMarker>>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    // Don't steal typing from the UI
+    if egui_context.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Home) {
+        fit.send(FitCameraEvent);
+    }
+    if keys.just_pressed(KeyCode::F) {
+        controls.follow = !controls.follow;
+    }
+
+    let (mut transform, mut projection) = camera.single_mut();
+    let mut dir = Vec2::ZERO;
+    if keys.any_pressed([KeyCode::W, KeyCode::Up]) {
+        dir.y += 1.0;
+    }
+    if keys.any_pressed([KeyCode::S, KeyCode::Down]) {
+        dir.y -= 1.0;
+    }
+    if keys.any_pressed([KeyCode::A, KeyCode::Left]) {
+        dir.x -= 1.0;
+    }
+    if keys.any_pressed([KeyCode::D, KeyCode::Right]) {
+        dir.x += 1.0;
+    }
+    if dir != Vec2::ZERO {
+        // Pan by a fraction of the visible area, so it feels the same at any zoom
+        let visible = (projection.right - projection.left).min(projection.top - projection.bottom)
+            * projection.scale;
+        let offset = dir.normalize() * visible * CAMERA_KEY_PAN_SPEED * time.delta_seconds();
+        transform.translation += offset.extend(0.0);
+        controls.follow = false;
+    }
+
+    let mut zoom = 0.0;
+    if keys.any_pressed([KeyCode::Q, KeyCode::Minus]) {
+        zoom -= 1.0;
+    }
+    if keys.any_pressed([KeyCode::E, KeyCode::Equals]) {
+        zoom += 1.0;
+    }
+    if zoom != 0.0 {
+        let factor = CAMERA_KEY_ZOOM.powf(-zoom * time.delta_seconds());
+        projection.scale = (projection.scale * factor).clamp(CAMERA_MIN_SCALE, CAMERA_MAX_SCALE);
+    }
+}
+
+/// System to pan the camera by dragging with the right or middle mouse button,
+/// keeping the world position that was grabbed under the cursor.
+fn camera_pan_system(
+    mouse: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    mut controls: ResMut<CameraControls>,
+    mut camera: Query<(&Camera, &GlobalTransform, &mut Transform), With<MainCameraMarker>>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    let buttons = [MouseButton::Right, MouseButton::Middle];
+    if !mouse.any_pressed(buttons) {
+        controls.drag_anchor = None;
+        return;
+    }
+
+    let (camera, global_transform, mut transform) = camera.single_mut();
+    let cursor_world = match windows
+        .get_primary()
+        .and_then(|window| window.cursor_position())
+        .and_then(|cursor| camera.viewport_to_world(global_transform, cursor))
+    {
+        Some(ray) => ray.origin.truncate(),
+        None => return,
+    };
+
+    // Start dragging unless the press was meant for the UI
+    if mouse.any_just_pressed(buttons) {
+        if !egui_context.ctx_mut().is_pointer_over_area() {
+            controls.drag_anchor = Some(cursor_world);
+            controls.follow = false;
+        }
+        return;
+    }
+
+    if let Some(anchor) = controls.drag_anchor {
+        transform.translation += (anchor - cursor_world).extend(0.0);
+    }
+}
+
+/// System to zoom the camera with the scroll wheel, keeping the world position
+/// under the cursor in place.
+fn camera_zoom_system(
+    mut scroll: EventReader<MouseWheel>,
+    windows: Res<Windows>,
+    controls: Res<CameraControls>,
+    mut camera: Query<
+        (
+            &Camera,
+            &GlobalTransform,
+            &mut Transform,
+            &mut OrthographicProjection,
+        ),
+        With<MainCameraMarker>,
+    >,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    let lines: f32 = scroll
+        .iter()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / CAMERA_PIXELS_PER_LINE,
+        })
+        .sum();
+    // Let the UI scroll its own windows
+    if lines == 0.0 || egui_context.ctx_mut().is_pointer_over_area() {
+        return;
+    }
+
+    let (camera, global_transform, mut transform, mut projection) = camera.single_mut();
+    let old_scale = projection.scale;
+    projection.scale =
+        (old_scale * CAMERA_SCROLL_ZOOM.powf(-lines)).clamp(CAMERA_MIN_SCALE, CAMERA_MAX_SCALE);
+
+    // Zoom around the cursor, unless the camera is following a Smitty
+    if controls.follow {
+        return;
+    }
+    let cursor_world = windows
+        .get_primary()
+        .and_then(|window| window.cursor_position())
+        .and_then(|cursor| camera.viewport_to_world(global_transform, cursor));
+    if let Some(ray) = cursor_world {
+        let anchor = ray.origin.truncate();
+        let center = transform.translation.truncate();
+        let new_center = anchor + (center - anchor) * (projection.scale / old_scale);
+        transform.translation = new_center.extend(transform.translation.z);
+    }
+}
+
+/// System to keep the camera centered on the selected Smitty when following.
+fn camera_follow_system(
+    controls: Res<CameraControls>,
+    selected_smitty: Res<SelectedSmitty>,
+    smittys: Query<&SimEntityPosRot>,
+    mut camera: Query<&mut Transform, With<MainCameraMarker>>,
+) {
+    if !controls.follow {
+        return;
+    }
+
+    if let Some(pos) = selected_smitty
+        .0
+        .and_then(|selected| smittys.get(selected).ok())
+    {
+        let mut transform = camera.single_mut();
+        transform.translation = pos.0.extend(transform.translation.z);
+    }
+}
@@ -0,0 +1,362 @@
+//! Simulation configuration, read from the command line.
+
+use bevy::prelude::*;
+use std::path::PathBuf;
+
+/// The usage message printed when the command line arguments are invalid.
+pub const USAGE: &str = "usage: fafevosim [--headless] [--frames <count>] [--seed <seed>]
+                 [--map <file>] [--export-dir <dir>] [--export-format <csv|jsonl>]
+                 [--export-interval <neural frames>] [--metabolic-exponent <exp>]
+                 [--speed-exponent <exp>] [--strength-exponent <exp>]
+                 [--season-period <seconds>] [--flood-range <tiles>]
+
+options:
+    --headless                  run the simulation without a window
+    --frames <count>            run the simulation for this many world frames,
+                                then pause (or exit, when headless)
+    --seed <seed>               the seed used to generate the world
+    --map <file>                load the world from a map file (or a .png
+                                image) instead of generating it
+    --export-dir <dir>          export statistics into a new run directory
+                                inside this directory
+    --export-format <format>    write exported files as csv (default) or jsonl
+    --export-interval <count>   export statistics every this many neural
+                                frames (default 1)
+    --metabolic-exponent <exp>  how a Smitty's metabolism scales with its body
+                                mass, as mass^exp (default 0.75)
+    --speed-exponent <exp>      how a Smitty's top speed scales with its body
+                                mass, as mass^exp (default -0.25)
+    --strength-exponent <exp>   how a Smitty's strength in a fight scales with
+                                its body mass, as mass^exp (default 0.67)
+    --season-period <seconds>   the length of a year of seasons in simulated
+                                seconds, or 0 for no seasons (default 300)
+    --flood-range <tiles>       how many tiles the water rises past its
+                                shoreline in winter and falls back from it in
+                                summer (default 1)";
+
+/// Possible errors returned when reading the configuration from the command
+/// line.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("unknown argument: {0}")]
+    UnknownArg(String),
+
+    #[error("missing value for {0}")]
+    MissingValue(&'static str),
+
+    #[error("invalid value for {0}: {1}")]
+    InvalidValue(&'static str, String),
+}
+
+/// The file formats statistics can be exported as.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExportFormat {
+    /// Comma separated values, with a header row.
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl ExportFormat {
+    /// Get the file extension for this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::JsonLines => "jsonl",
+        }
+    }
+}
+
+/// The exponents of the allometric formulas that scale a Smitty's abilities
+/// with its body mass (relative to a standard Smitty), as `mass^exponent`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Allometry {
+    /// How the energy spent on metabolism scales with mass.
+    pub metabolic_exponent: f32,
+    /// How the top movement speed scales with mass.
+    pub speed_exponent: f32,
+    /// How strength in a fight scales with mass.
+    pub strength_exponent: f32,
+}
+
+impl Default for Allometry {
+    fn default() -> Self {
+        Self {
+            metabolic_exponent: 0.75,
+            speed_exponent: -0.25,
+            strength_exponent: 0.67,
+        }
+    }
+}
+
+/// Resource containing the configuration the simulation was started with.
+#[derive(Debug, Clone, Resource)]
+pub struct SimConfig {
+    /// Whether to run without a window or any rendering.
+    pub headless: bool,
+    /// The number of world frames to run before pausing, or `None` to wait for
+    /// the user (or run forever, when headless).
+    pub frames: Option<u32>,
+    /// The seed used to generate the world.
+    pub seed: u32,
+    /// The map file to load the world from, or `None` to generate it.
+    pub map: Option<PathBuf>,
+    /// The directory to create the run directory for exported statistics in,
+    /// or `None` to not export anything.
+    pub export_dir: Option<PathBuf>,
+    /// The format to export statistics in.
+    pub export_format: ExportFormat,
+    /// The number of neural frames between each exported sample.
+    pub export_interval: u32,
+    /// How the Smittys' abilities scale with their body mass.
+    pub allometry: Allometry,
+    /// The length of a year of seasons in simulated seconds, or 0.0 for the
+    /// seasons to never change.
+    pub season_period: f32,
+    /// How many tiles the water rises past its shoreline in winter and falls
+    /// back from it in summer.
+    pub flood_range: u32,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            headless: false,
+            frames: None,
+            seed: 0,
+            map: None,
+            export_dir: None,
+            export_format: default(),
+            export_interval: 1,
+            allometry: default(),
+            season_period: 300.0,
+            flood_range: 1,
+        }
+    }
+}
+
+impl SimConfig {
+    /// Read the configuration from the given command line arguments (without
+    /// the program name).
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => config.headless = true,
+                "--frames" => config.frames = Some(parse_value(&mut args, "--frames")?),
+                "--seed" => config.seed = parse_value(&mut args, "--seed")?,
+                "--map" => {
+                    let value = args.next().ok_or(ConfigError::MissingValue("--map"))?;
+                    config.map = Some(value.into());
+                }
+                "--export-dir" => {
+                    let value = args
+                        .next()
+                        .ok_or(ConfigError::MissingValue("--export-dir"))?;
+                    config.export_dir = Some(value.into());
+                }
+                "--export-format" => {
+                    let value = args
+                        .next()
+                        .ok_or(ConfigError::MissingValue("--export-format"))?;
+                    config.export_format = match value.as_str() {
+                        "csv" => ExportFormat::Csv,
+                        "jsonl" => ExportFormat::JsonLines,
+                        _ => return Err(ConfigError::InvalidValue("--export-format", value)),
+                    };
+                }
+                "--export-interval" => {
+                    let value = args
+                        .next()
+                        .ok_or(ConfigError::MissingValue("--export-interval"))?;
+                    config.export_interval = match value.parse() {
+                        Ok(interval) if interval > 0 => interval,
+                        _ => return Err(ConfigError::InvalidValue("--export-interval", value)),
+                    };
+                }
+                "--metabolic-exponent" => {
+                    config.allometry.metabolic_exponent =
+                        parse_finite(&mut args, "--metabolic-exponent")?
+                }
+                "--speed-exponent" => {
+                    config.allometry.speed_exponent = parse_finite(&mut args, "--speed-exponent")?
+                }
+                "--strength-exponent" => {
+                    config.allometry.strength_exponent =
+                        parse_finite(&mut args, "--strength-exponent")?
+                }
+                "--season-period" => {
+                    let value = args
+                        .next()
+                        .ok_or(ConfigError::MissingValue("--season-period"))?;
+                    config.season_period = match value.parse::<f32>() {
+                        Ok(period) if period >= 0.0 && period.is_finite() => period,
+                        _ => return Err(ConfigError::InvalidValue("--season-period", value)),
+                    };
+                }
+                "--flood-range" => config.flood_range = parse_value(&mut args, "--flood-range")?,
+                _ => return Err(ConfigError::UnknownArg(arg)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Read and parse the value following an option.
+fn parse_value<T: std::str::FromStr>(
+    args: &mut impl Iterator<Item = String>,
+    name: &'static str,
+) -> Result<T, ConfigError> {
+    let value = args.next().ok_or(ConfigError::MissingValue(name))?;
+    value
+        .parse()
+        .map_err(|_| ConfigError::InvalidValue(name, value))
+}
+
+/// Read and parse the number following an option, which must be finite.
+fn parse_finite(
+    args: &mut impl Iterator<Item = String>,
+    name: &'static str,
+) -> Result<f32, ConfigError> {
+    let value = args.next().ok_or(ConfigError::MissingValue(name))?;
+    match value.parse::<f32>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(ConfigError::InvalidValue(name, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<SimConfig, ConfigError> {
+        SimConfig::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn no_args_is_the_default() {
+        let config = parse(&[]).unwrap();
+        let default = SimConfig::default();
+        assert_eq!(config.headless, default.headless);
+        assert_eq!(config.frames, default.frames);
+        assert_eq!(config.seed, default.seed);
+        assert_eq!(config.allometry, default.allometry);
+        assert_eq!(config.season_period, default.season_period);
+    }
+
+    #[test]
+    fn valid_args() {
+        let config = parse(&[
+            "--headless",
+            "--frames",
+            "100",
+            "--seed",
+            "42",
+            "--map",
+            "world.png",
+            "--export-dir",
+            "runs",
+            "--export-format",
+            "jsonl",
+            "--export-interval",
+            "5",
+            "--metabolic-exponent",
+            "0.5",
+            "--speed-exponent",
+            "-1",
+            "--strength-exponent",
+            "1e-1",
+            "--season-period",
+            "0",
+            "--flood-range",
+            "3",
+        ])
+        .unwrap();
+        assert!(config.headless);
+        assert_eq!(config.frames, Some(100));
+        assert_eq!(config.seed, 42);
+        assert_eq!(config.map, Some("world.png".into()));
+        assert_eq!(config.export_dir, Some("runs".into()));
+        assert_eq!(config.export_format, ExportFormat::JsonLines);
+        assert_eq!(config.export_interval, 5);
+        assert_eq!(
+            config.allometry,
+            Allometry {
+                metabolic_exponent: 0.5,
+                speed_exponent: -1.0,
+                strength_exponent: 0.1,
+            }
+        );
+        assert_eq!(config.season_period, 0.0);
+        assert_eq!(config.flood_range, 3);
+    }
+
+    #[test]
+    fn unknown_arg() {
+        for arg in ["--bogus", "headless", "-h", ""] {
+            assert!(
+                matches!(parse(&[arg]), Err(ConfigError::UnknownArg(found)) if found == arg),
+                "{:?} accepted",
+                arg
+            );
+        }
+        // Options' values aren't mistaken for options, and vice versa
+        assert!(matches!(
+            parse(&["--seed", "1", "2"]),
+            Err(ConfigError::UnknownArg(found)) if found == "2"
+        ));
+    }
+
+    #[test]
+    fn missing_value() {
+        for option in [
+            "--frames",
+            "--seed",
+            "--map",
+            "--export-dir",
+            "--export-format",
+            "--export-interval",
+            "--metabolic-exponent",
+            "--speed-exponent",
+            "--strength-exponent",
+            "--season-period",
+            "--flood-range",
+        ] {
+            assert!(
+                matches!(parse(&[option]), Err(ConfigError::MissingValue(name)) if name == option),
+                "{} accepted without a value",
+                option
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_value() {
+        for (option, value) in [
+            ("--frames", "-1"),
+            ("--frames", "ten"),
+            ("--seed", "1.5"),
+            ("--export-format", "xml"),
+            ("--export-interval", "0"),
+            ("--metabolic-exponent", "NaN"),
+            ("--speed-exponent", "inf"),
+            ("--strength-exponent", "-inf"),
+            ("--strength-exponent", "strong"),
+            ("--season-period", "-1"),
+            ("--season-period", "NaN"),
+            ("--season-period", "inf"),
+            ("--flood-range", "-1"),
+        ] {
+            assert!(
+                matches!(
+                    parse(&[option, value]),
+                    Err(ConfigError::InvalidValue(name, found)) if name == option && found == value
+                ),
+                "{} {} accepted",
+                option,
+                value
+            );
+        }
+    }
+}
@@ -1,22 +1,129 @@
 //! The ECS components and systems.
 
-use crate::{net::*, simworld::WORLD_SIZE};
-use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
+use crate::{
+    config::{Allometry, SimConfig},
+    net::*,
+    scent::{ScentChannel, ScentSense, SMITTY_PHEROMONE_DRAIN},
+    season::Season,
+    signal::SignalSense,
+    simworld::{update_food_system, SimWorld, MAX_MEAT},
+    spatial::SpatialIndex,
+    vision::{
+        VisionRay, MAX_VISION_RAYS, SMITTY_MAX_VISION_FOV, SMITTY_MAX_VISION_RANGE,
+        SMITTY_MIN_VISION_FOV, SMITTY_MIN_VISION_RANGE, SMITTY_VISION_RAY_DRAIN,
+    },
+};
+use bevy::{
+    ecs::schedule::ShouldRun, prelude::*, sprite::MaterialMesh2dBundle, transform::TransformSystem,
+    utils::Instant,
+};
 use iyes_loopless::prelude::*;
-use std::{f32::consts::PI, time::Duration};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::{HashMap, HashSet},
+    f32::consts::PI,
+    time::Duration,
+};
 
 /// Neural network update systems fixed timestep name.
 pub const FT_NEURAL_UPDATE: &str = "fixed_timestep_start_neural_update";
 /// The number of world tick frames between each feed-forward execution through
 /// the neural networks.
 pub const NETWORK_UPDATE_PERIOD: u32 = 60;
+/// The simulated time (in seconds) that passes during each world frame.
+pub const FRAME_DELTA_SECONDS: f32 = 1.0 / 60.0;
+/// The slowest speed multiplier the simulation may run at.
+pub const SIM_MIN_SPEED: f32 = 0.25;
+/// The fastest speed multiplier the simulation may run at (short of max).
+pub const SIM_MAX_SPEED: f32 = 1000.0;
+/// The most real time that may be spent running simulation ticks each rendered
+/// frame, so the UI stays responsive at high speeds.
+pub const SIM_TICK_BUDGET: Duration = Duration::from_millis(25);
 
-/// The scale of a Smitty.
+/// The scale of a Smitty of size 1.0.
 pub const SMITTY_SCALE: f32 = 1.0;
+/// The largest size a Smitty may grow to.
+pub const SMITTY_MAX_SIZE: f32 = 2.0;
+/// The smallest size a Smitty may shrink to.
+pub const SMITTY_MIN_SIZE: f32 = 0.25;
+/// The radius (in meters) of the biggest possible Smitty.
+pub const SMITTY_MAX_RADIUS: f32 = 0.5 * SMITTY_SCALE * SMITTY_MAX_SIZE;
 /// The maximum allowed speed in meters per second a Smitty may move.
 pub const SMITTY_MAX_MOVE_SPEED: f32 = 4.0;
+/// How far (in meters) past the biggest possible overlap to look for Smittys
+/// that may be colliding, since the spatial index lags a frame behind.
+const SMITTY_COLLISION_MARGIN: f32 = 2.0 * SMITTY_MAX_MOVE_SPEED * FRAME_DELTA_SECONDS;
 /// The maximum radians per second a smitty may rotate.
 pub const SMITTY_MAX_ROT_SPEED: f32 = 8.0 * PI; // 4 rot/s
+/// The maximum amount of energy a Smitty can hold.
+pub const SMITTY_MAX_ENERGY: f32 = 1.0;
+/// The energy per second a Smitty of mass 1.0 spends just by being alive.
+pub const SMITTY_BASE_ENERGY_DRAIN: f32 = 0.005;
+/// The energy per second a Smitty of mass 1.0 spends moving at
+/// `SMITTY_MAX_MOVE_SPEED`, which grows with the square of its speed.
+pub const SMITTY_MOVE_ENERGY_DRAIN: f32 = 0.01;
+/// The lightest a Smitty may be built for its size.
+pub const SMITTY_MIN_DENSITY: f32 = 0.5;
+/// The heaviest a Smitty may be built for its size.
+pub const SMITTY_MAX_DENSITY: f32 = 1.5;
+/// How far away (in meters) a Smitty can reach another Smitty to attack it.
+pub const SMITTY_ATTACK_RANGE: f32 = 1.0;
+/// The energy an attack takes from an unarmoured Smitty of equal size.
+pub const SMITTY_ATTACK_DAMAGE: f32 = 0.25;
+/// The energy it costs a Smitty to attack.
+pub const SMITTY_ATTACK_COST: f32 = 0.02;
+/// The portion of the energy taken from the victim of an attack that the
+/// attacker gains.
+pub const SMITTY_ATTACK_EFFICIENCY: f32 = 0.5;
+/// The portion of a Smitty's speed lost when it is fully armoured.
+pub const SMITTY_ARMOUR_SPEED_PENALTY: f32 = 0.5;
+/// The food per second a Smitty can eat from each layer of food on its tile.
+pub const SMITTY_EAT_RATE: f32 = 0.1;
+/// The meat left behind by the carcass of a Smitty of mass 1.0.
+pub const SMITTY_CARCASS_MEAT: f32 = 0.5;
+/// The shortest (in seconds) a Smitty's lifespan may be.
+pub const SMITTY_MIN_LIFESPAN: f32 = 120.0;
+/// The longest (in seconds) a Smitty's lifespan may be.
+pub const SMITTY_MAX_LIFESPAN: f32 = 600.0;
+/// The portion of its lifespan a Smitty must live before it can reproduce.
+pub const SMITTY_MATURITY: f32 = 0.1;
+/// The portion of its lifespan after which a Smitty starts slowing down.
+pub const SMITTY_DECLINE_START: f32 = 0.6;
+/// The speed multiplier of a Smitty at the very end of its lifespan.
+pub const SMITTY_OLD_AGE_SPEED_MULT: f32 = 0.5;
+/// The energy a Smitty needs before it can reproduce.
+pub const SMITTY_REPRODUCE_MIN_ENERGY: f32 = 0.6;
+/// The energy a parent hands over to its child, which the child starts with.
+pub const SMITTY_CHILD_ENERGY: f32 = 0.3;
+/// The energy lost giving birth, on top of what's handed to the child.
+pub const SMITTY_REPRODUCE_COST: f32 = 0.05;
+/// How far behind its parent (in meters, for a parent of size 1.0) a child is
+/// born.
+pub const SMITTY_BIRTH_DISTANCE: f32 = 0.5;
+/// The brain output above which a discrete action (eating, reproducing or
+/// attacking) is performed.
+pub const ACTION_THRESHOLD: f32 = 0.5;
+/// The number of nodes in the hidden layer of a Smitty's brain, which grows
+/// with the number of inputs so that more senses aren't squeezed through the
+/// same few nodes.
+pub const BRAIN_HIDDEN_NODES: usize =
+    (SimEntityBrainInputs::COUNT + SimEntityBrainOutputs::COUNT) / 3;
+/// The chance that each weight in a child's brain is mutated.
+pub const MUTATION_RATE: f64 = 0.1;
+/// The most a mutation may change a brain weight, or a trait as a fraction of
+/// its range.
+pub const MUTATION_STRENGTH: f32 = 0.2;
+/// How different (on average, as a fraction of each trait's range) a child's
+/// traits must be from its parent's for it to found a new species.
+pub const SPECIATION_DISTANCE: f32 = 0.1;
+/// The number of Smittys spawned into a new world.
+pub const INITIAL_SMITTY_COUNT: usize = 20;
+
+/// The stage containing the simulation schedule, which runs its stages once
+/// per simulation tick, for as many ticks as the `SimSpeed` calls for each
+/// rendered frame.
+#[derive(Debug, Copy, Clone, StageLabel)]
+pub struct SimulationStage;
 
 /// The stages within a frame update
 #[derive(Debug, Copy, Clone, StageLabel)]
@@ -27,6 +134,10 @@ pub enum FrameUpdateStage {
     UpdateNeural,
     /// Move entities according to their last brain outputs.
     UpdateEntities,
+    /// Remove entities that died during this frame.
+    Cleanup,
+    /// Rebuild the spatial index with where entities ended up.
+    UpdateIndex,
 }
 
 /// The stages within the update stage for the neural network simulation.
@@ -36,7 +147,8 @@ pub enum NeuralUpdateStage {
     Collect,
     /// The stage in which entities move and interact.
     Update,
-    /// The stage in which the world is updated.
+    /// The stage in which brain outputs are turned into actions, and the
+    /// actions that happen once per neural update are performed.
     Perform,
 }
 
@@ -46,43 +158,551 @@ pub enum NeuralUpdateStage {
 pub struct SimEntityBrain {
     /// The neural network.
     pub network: NN<f32>,
+    /// The values of every layer of the network from its last run, from the
+    /// inputs through to the outputs. Empty until the network first runs.
+    pub activations: Vec<Vec<f32>>,
 }
 
 impl SimEntityBrain {
-    pub fn random() -> Self {
+    pub fn random(rng: &mut impl Rng) -> Self {
         Self {
-            // Network with 1 input and 2 outputs
-            network: NN::random(&[2, 3, 2]).unwrap(),
+            // Network with one input node for each brain input, a hidden
+            // layer, and one output node for each action
+            network: NN::random(
+                &[
+                    SimEntityBrainInputs::COUNT as u32,
+                    BRAIN_HIDDEN_NODES as u32,
+                    SimEntityBrainOutputs::COUNT as u32,
+                ],
+                rng,
+            )
+            .unwrap(),
+            activations: Vec::new(),
+        }
+    }
+
+    /// Create a copy of this brain with some of its weights mutated, for a
+    /// child.
+    pub fn mutated(&self, rng: &mut impl Rng) -> Self {
+        let mut network = self.network.clone();
+        network.mutate(MUTATION_RATE, MUTATION_STRENGTH, rng);
+        Self {
+            network,
+            activations: Vec::new(),
         }
     }
 }
 
 /// Component containing position and rotation of the entity (Smitty) in the
 /// simulation world.
-/// The position should be bound to the limited world (probably just clamped).
+/// The position is kept within the world according to its `WorldBoundary`.
 #[derive(Debug, Component)]
 pub struct SimEntityPosRot(pub Vec2, pub f32);
 
 /// The inputs for the Smitty's brain.
-#[derive(Debug, Component)]
-pub struct SimEntityBrainInputs {}
+#[derive(Default, Debug, Component)]
+pub struct SimEntityBrainInputs {
+    /// The entity's energy as a fraction of its maximum energy.
+    pub energy: f32,
+    /// The entity's age as a fraction of its lifespan.
+    pub age: f32,
+    /// How warm it is, from -1.0 (midwinter) to 1.0 (midsummer).
+    pub temperature: f32,
+    /// Whether the world is warming up or cooling down, from -1.0 (autumn) to
+    /// 1.0 (spring).
+    pub season: f32,
+    /// The fraction of the maximum food currently on the tile underfoot.
+    pub tile_food: f32,
+    /// How far other Smittys are pressing into the entity's body, from 0.0
+    /// (not touching) towards 1.0.
+    pub touching: f32,
+    /// How close the nearest visible Smitty is, from 0.0 (out of sight) to 1.0
+    /// (right on top of this one).
+    pub nearest_proximity: f32,
+    /// The direction to the nearest visible Smitty relative to this entity's
+    /// heading, from -1.0 (behind, turning clockwise) to 1.0 (behind, turning
+    /// counter-clockwise).
+    pub nearest_bearing: f32,
+    /// What each of the entity's vision rays saw, from its right to its left.
+    /// Rays the entity doesn't have see nothing.
+    pub vision: [VisionRay; MAX_VISION_RAYS],
+    /// What the entity smells of each scent channel.
+    pub scent: [ScentSense; ScentChannel::COUNT],
+    /// What the entity hears of the signals around it.
+    pub signal: SignalSense,
+}
 
-/// The requested move & rotation speeds.
-#[derive(Debug, Component)]
-pub struct SimEntityBrainOutputs {
-    /// The percentage of this entity's max speed that it wishes to travel.
+impl SimEntityBrainInputs {
+    /// The number of inputs to the brain.
+    pub const COUNT: usize = Self::SCENT_START + ScentChannel::COUNT * ScentSense::COUNT;
+    /// The names of the inputs that aren't from the senses, in the order
+    /// they're fed into the brain.
+    const NAMES: [&'static str; 11] = [
+        "Energy",
+        "Age",
+        "Temperature",
+        "Season",
+        "Tile food",
+        "Touching",
+        "Nearest proximity",
+        "Nearest bearing",
+        "Signal strength",
+        "Signal tone",
+        "Signal bearing",
+    ];
+    /// The index of the first scent input.
+    const SCENT_START: usize = Self::VISION_START + MAX_VISION_RAYS * VisionRay::COUNT;
+    /// The index of the first vision ray input.
+    const VISION_START: usize = Self::NAMES.len();
+
+    /// Get the name of the input fed into the given brain node, or `None` if
+    /// there's no such input.
+    pub fn name(node: usize) -> Option<String> {
+        match node {
+            node if node < Self::VISION_START => Some(Self::NAMES[node].to_owned()),
+            node if node < Self::SCENT_START => {
+                let node = node - Self::VISION_START;
+                Some(format!(
+                    "Ray {} {}",
+                    node / VisionRay::COUNT + 1,
+                    VisionRay::NAMES[node % VisionRay::COUNT]
+                ))
+            }
+            node if node < Self::COUNT => {
+                let node = node - Self::SCENT_START;
+                Some(format!(
+                    "{} {}",
+                    ScentChannel::ALL[node / ScentSense::COUNT].name(),
+                    ScentSense::NAMES[node % ScentSense::COUNT]
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the inputs in the order they're fed into the brain.
+    pub fn to_array(&self) -> [f32; Self::COUNT] {
+        let mut inputs = [0.0; Self::COUNT];
+        let (base, senses) = inputs.split_at_mut(Self::VISION_START);
+        let (vision, scent) = senses.split_at_mut(Self::SCENT_START - Self::VISION_START);
+        base.copy_from_slice(&[
+            self.energy,
+            self.age,
+            self.temperature,
+            self.season,
+            self.tile_food,
+            self.touching,
+            self.nearest_proximity,
+            self.nearest_bearing,
+            self.signal.strength,
+            self.signal.tone,
+            self.signal.bearing,
+        ]);
+        for (chunk, ray) in vision.chunks_exact_mut(VisionRay::COUNT).zip(&self.vision) {
+            chunk.copy_from_slice(&ray.to_array());
+        }
+        for (chunk, sense) in scent.chunks_exact_mut(ScentSense::COUNT).zip(&self.scent) {
+            chunk.copy_from_slice(&sense.to_array());
+        }
+        inputs
+    }
+}
+
+/// The things a Smitty can do, each driven by one of its brain's outputs.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum SmittyAction {
+    /// Move forward.
+    Move,
+    /// Turn left or right.
+    Turn,
+    /// Eat the food on the tile underfoot.
+    Eat,
+    /// Give birth to a mutated child.
+    Reproduce,
+    /// Attack the nearest Smitty within reach.
+    Attack,
+    /// Give off a signal.
+    Signal,
+    /// Pick the tone of the signal.
+    SignalTone,
+    /// Lay down the first pheromone.
+    PheromoneA,
+    /// Lay down the second pheromone.
+    PheromoneB,
+}
+
+impl SmittyAction {
+    /// All of the actions, in the order of the brain outputs driving them.
+    pub const ALL: [Self; 9] = [
+        Self::Move,
+        Self::Turn,
+        Self::Eat,
+        Self::Reproduce,
+        Self::Attack,
+        Self::Signal,
+        Self::SignalTone,
+        Self::PheromoneA,
+        Self::PheromoneB,
+    ];
+
+    /// Get the name of this action to show the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Move => "Move",
+            Self::Turn => "Turn",
+            Self::Eat => "Eat",
+            Self::Reproduce => "Reproduce",
+            Self::Attack => "Attack",
+            Self::Signal => "Signal",
+            Self::SignalTone => "Signal tone",
+            Self::PheromoneA => "Pheromone A",
+            Self::PheromoneB => "Pheromone B",
+        }
+    }
+}
+
+/// The raw outputs of the Smitty's brain, one for each `SmittyAction`.
+#[derive(Default, Debug, Component)]
+pub struct SimEntityBrainOutputs(pub [f32; Self::COUNT]);
+
+impl SimEntityBrainOutputs {
+    /// The number of outputs from the brain.
+    pub const COUNT: usize = SmittyAction::ALL.len();
+
+    /// Get the output driving the given action.
+    pub fn get(&self, action: SmittyAction) -> f32 {
+        self.0[action as usize]
+    }
+}
+
+/// Component containing the actions a Smitty decided on at its last neural
+/// update.
+#[derive(Default, Debug, Copy, Clone, Component)]
+pub struct SmittyActions {
+    /// The fraction of its max speed the Smitty moves forward at.
     pub move_amt: f32,
-    /// The percentage of this entity's max rotation speed that it wishes to rotate.
-    pub rot_amt: f32,
+    /// The fraction of its max rotation speed the Smitty turns at, from -1.0
+    /// (clockwise) to 1.0 (counter-clockwise).
+    pub turn_amt: f32,
+    /// Whether the Smitty eats the food on the tile underfoot.
+    pub eat: bool,
+    /// Whether the Smitty gives birth, if it has the energy.
+    pub reproduce: bool,
+    /// Whether the Smitty attacks the nearest Smitty within reach.
+    pub attack: bool,
+    /// The strength of the signal the Smitty gives off, from 0.0 (silent) to
+    /// 1.0.
+    pub signal: f32,
+    /// The tone of the signal the Smitty gives off, from 0.0 to 1.0, which
+    /// sets its color.
+    pub signal_tone: f32,
+    /// How strongly the Smitty lays down each pheromone, from 0.0 (not at
+    /// all) to 1.0, in the order of `ScentChannel::PHEROMONES`.
+    pub pheromones: [f32; 2],
+}
+
+impl SmittyActions {
+    /// Decide on the actions for a set of brain outputs.
+    pub fn from_outputs(outputs: &SimEntityBrainOutputs) -> Self {
+        // Only outputs above the threshold count for some actions, so Smittys
+        // can stop doing them entirely
+        let above_threshold =
+            |action| ((outputs.get(action) - ACTION_THRESHOLD) / (1.0 - ACTION_THRESHOLD)).max(0.0);
+        Self {
+            move_amt: outputs.get(SmittyAction::Move),
+            turn_amt: outputs.get(SmittyAction::Turn) * 2.0 - 1.0,
+            eat: outputs.get(SmittyAction::Eat) > ACTION_THRESHOLD,
+            reproduce: outputs.get(SmittyAction::Reproduce) > ACTION_THRESHOLD,
+            attack: outputs.get(SmittyAction::Attack) > ACTION_THRESHOLD,
+            signal: above_threshold(SmittyAction::Signal),
+            signal_tone: outputs.get(SmittyAction::SignalTone),
+            pheromones: [SmittyAction::PheromoneA, SmittyAction::PheromoneB].map(above_threshold),
+        }
+    }
 }
 
 /// Component containing inherited traits for entities in the simulation.
-#[derive(Debug, Component)]
+#[derive(Default, Debug, Copy, Clone, Component)]
 pub struct SimEntityTraits {
     /// The maximum speed of this entity (in meters per second).
     pub max_move_speed: f32,
     /// The maximum speed the entity can rotate (in radians per second).
     pub max_rot_speed: f32,
+    /// How well the entity swims, from 0.0 (sluggish and tiring in water) to
+    /// 1.0 (at home in water, but slower on land).
+    pub swim_ability: f32,
+    /// The size of the entity relative to a standard Smitty, which sets how
+    /// big it's drawn and (with its density) its mass.
+    pub size: f32,
+    /// How heavily the entity is built for its size, relative to a standard
+    /// Smitty. Heavier entities hit harder but are slower and burn more
+    /// energy.
+    pub density: f32,
+    /// The hue of the entity's body, from 0.0 to 1.0 around the color wheel.
+    pub hue: f32,
+    /// How well the entity is protected from attacks, from 0.0 (not at all) to
+    /// 1.0 (invulnerable, but slow).
+    pub armour: f32,
+    /// What the entity eats, from 0.0 (only plants) to 1.0 (only meat).
+    pub diet: f32,
+    /// The number of vision rays the entity casts, rounded to the nearest
+    /// whole ray.
+    pub vision_rays: f32,
+    /// How far (in meters) the entity's senses reach, both its vision rays and
+    /// noticing the nearest Smitty.
+    pub vision_range: f32,
+    /// The angle (in radians) the entity's vision rays are spread across.
+    pub vision_fov: f32,
+    /// How long (in seconds) the entity lives, if nothing kills it first.
+    pub lifespan: f32,
+}
+
+impl SimEntityTraits {
+    /// Create a random set of traits.
+    pub fn random(rng: &mut impl Rng) -> Self {
+        Self {
+            max_move_speed: rng.gen_range(0.1..=1.0) * SMITTY_MAX_MOVE_SPEED,
+            max_rot_speed: rng.gen_range(0.1..=1.0) * SMITTY_MAX_ROT_SPEED,
+            swim_ability: rng.gen_range(0.0..=1.0),
+            size: rng.gen_range(0.5..=1.0),
+            density: rng.gen_range(SMITTY_MIN_DENSITY..=SMITTY_MAX_DENSITY),
+            hue: rng.gen_range(0.0..=1.0),
+            armour: rng.gen_range(0.0..=0.5),
+            diet: rng.gen_range(0.0..=1.0),
+            vision_rays: rng.gen_range(0.0..=MAX_VISION_RAYS as f32),
+            vision_range: rng.gen_range(SMITTY_MIN_VISION_RANGE..=SMITTY_MAX_VISION_RANGE),
+            vision_fov: rng.gen_range(SMITTY_MIN_VISION_FOV..=SMITTY_MAX_VISION_FOV),
+            lifespan: rng.gen_range(SMITTY_MIN_LIFESPAN..=SMITTY_MAX_LIFESPAN),
+        }
+    }
+
+    /// Create a copy of these traits with each one nudged a little, for a
+    /// child.
+    pub fn mutated(&self, rng: &mut impl Rng) -> Self {
+        let mut mutate = |value: f32, min: f32, max: f32| {
+            let strength = MUTATION_STRENGTH * (max - min);
+            (value + rng.gen_range(-strength..=strength)).clamp(min, max)
+        };
+        Self {
+            max_move_speed: mutate(
+                self.max_move_speed,
+                0.1 * SMITTY_MAX_MOVE_SPEED,
+                SMITTY_MAX_MOVE_SPEED,
+            ),
+            max_rot_speed: mutate(
+                self.max_rot_speed,
+                0.1 * SMITTY_MAX_ROT_SPEED,
+                SMITTY_MAX_ROT_SPEED,
+            ),
+            swim_ability: mutate(self.swim_ability, 0.0, 1.0),
+            size: mutate(self.size, SMITTY_MIN_SIZE, SMITTY_MAX_SIZE),
+            density: mutate(self.density, SMITTY_MIN_DENSITY, SMITTY_MAX_DENSITY),
+            // The hue goes around the color wheel, so it wraps rather than
+            // sticking at either end
+            hue: (self.hue + mutate(0.0, -0.5, 0.5)).rem_euclid(1.0),
+            armour: mutate(self.armour, 0.0, 1.0),
+            diet: mutate(self.diet, 0.0, 1.0),
+            vision_rays: mutate(self.vision_rays, 0.0, MAX_VISION_RAYS as f32),
+            vision_range: mutate(
+                self.vision_range,
+                SMITTY_MIN_VISION_RANGE,
+                SMITTY_MAX_VISION_RANGE,
+            ),
+            vision_fov: mutate(
+                self.vision_fov,
+                SMITTY_MIN_VISION_FOV,
+                SMITTY_MAX_VISION_FOV,
+            ),
+            lifespan: mutate(self.lifespan, SMITTY_MIN_LIFESPAN, SMITTY_MAX_LIFESPAN),
+        }
+    }
+
+    /// Get how different these traits are from another set, as the average
+    /// difference between each trait relative to its range.
+    pub fn distance(&self, other: &Self) -> f32 {
+        let diffs = [
+            (self.max_move_speed - other.max_move_speed).abs() / (0.9 * SMITTY_MAX_MOVE_SPEED),
+            (self.max_rot_speed - other.max_rot_speed).abs() / (0.9 * SMITTY_MAX_ROT_SPEED),
+            (self.swim_ability - other.swim_ability).abs(),
+            (self.size - other.size).abs() / (SMITTY_MAX_SIZE - SMITTY_MIN_SIZE),
+            (self.density - other.density).abs() / (SMITTY_MAX_DENSITY - SMITTY_MIN_DENSITY),
+            {
+                // The hue wraps, so hues are at most half the wheel apart
+                let d = (self.hue - other.hue).abs();
+                2.0 * d.min(1.0 - d)
+            },
+            (self.armour - other.armour).abs(),
+            (self.diet - other.diet).abs(),
+            (self.vision_rays - other.vision_rays).abs() / MAX_VISION_RAYS as f32,
+            (self.vision_range - other.vision_range).abs()
+                / (SMITTY_MAX_VISION_RANGE - SMITTY_MIN_VISION_RANGE),
+            (self.vision_fov - other.vision_fov).abs()
+                / (SMITTY_MAX_VISION_FOV - SMITTY_MIN_VISION_FOV),
+            (self.lifespan - other.lifespan).abs() / (SMITTY_MAX_LIFESPAN - SMITTY_MIN_LIFESPAN),
+        ];
+        diffs.iter().sum::<f32>() / diffs.len() as f32
+    }
+
+    /// Get the mass of this entity relative to a standard Smitty, which grows
+    /// with the cube of its size.
+    pub fn mass(&self) -> f32 {
+        self.density * self.size.powi(3)
+    }
+
+    /// Get the radius (in meters) of this entity's body.
+    pub fn radius(&self) -> f32 {
+        0.5 * SMITTY_SCALE * self.size
+    }
+
+    /// Get the energy per second this entity spends just by being alive.
+    pub fn metabolic_drain(&self, allometry: &Allometry) -> f32 {
+        SMITTY_BASE_ENERGY_DRAIN * self.mass().powf(allometry.metabolic_exponent)
+    }
+
+    /// Get the top speed (in meters per second) this entity's body allows,
+    /// before any terrain or age penalties.
+    pub fn top_speed(&self, allometry: &Allometry) -> f32 {
+        (self.max_move_speed * self.mass().powf(allometry.speed_exponent))
+            .min(SMITTY_MAX_MOVE_SPEED)
+    }
+
+    /// Get how strong this entity is in a fight, relative to a standard
+    /// Smitty.
+    pub fn strength(&self, allometry: &Allometry) -> f32 {
+        self.mass().powf(allometry.strength_exponent)
+    }
+
+    /// Get the color of this entity's body.
+    pub fn color(&self) -> Color {
+        Color::hsl(self.hue.clamp(0.0, 1.0) * 360.0, 0.6, 0.7)
+    }
+
+    /// Get the number of vision rays this entity casts.
+    pub fn vision_ray_count(&self) -> usize {
+        (self.vision_rays.round().max(0.0) as usize).min(MAX_VISION_RAYS)
+    }
+
+    /// Get the energy per second this entity spends seeing, which grows with
+    /// the number of rays, how far they reach and how widely they're spread.
+    pub fn vision_energy_drain(&self) -> f32 {
+        SMITTY_VISION_RAY_DRAIN
+            * self.vision_ray_count() as f32
+            * (self.vision_range / SMITTY_MAX_VISION_RANGE)
+            * (self.vision_fov / SMITTY_MAX_VISION_FOV)
+    }
+
+    /// Get the portion of the energy in plant food this entity can digest.
+    pub fn plant_efficiency(&self) -> f32 {
+        1.0 - self.diet.clamp(0.0, 1.0)
+    }
+
+    /// Get the portion of the energy in meat this entity can digest.
+    pub fn meat_efficiency(&self) -> f32 {
+        self.diet.clamp(0.0, 1.0)
+    }
+}
+
+/// Component containing the energy a Smitty has left to spend.
+#[derive(Debug, Component)]
+pub struct SimEntityEnergy(pub f32);
+
+/// Component containing the species a Smitty belongs to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Component)]
+pub struct SimEntitySpecies(pub u32);
+
+/// Resource containing the random number generator everything random about
+/// the Smittys is drawn from, seeded from the configured seed so a run can be
+/// reproduced.
+#[derive(Resource)]
+pub struct SimRng(pub StdRng);
+
+impl FromWorld for SimRng {
+    fn from_world(world: &mut World) -> Self {
+        let seed = world
+            .get_resource::<SimConfig>()
+            .map_or(0, |config| config.seed);
+        Self(StdRng::seed_from_u64(seed as u64))
+    }
+}
+
+/// Resource handing out the IDs of new species.
+#[derive(Default, Debug, Resource)]
+pub struct SpeciesRegistry {
+    next_id: u32,
+}
+
+impl SpeciesRegistry {
+    /// Get a species that no other Smitty belongs to yet.
+    pub fn new_species(&mut self) -> SimEntitySpecies {
+        let species = SimEntitySpecies(self.next_id);
+        self.next_id += 1;
+        species
+    }
+}
+
+/// Component containing the number of world frames a Smitty has been alive.
+#[derive(Default, Debug, Copy, Clone, Component)]
+pub struct SimEntityAge(pub u32);
+
+impl SimEntityAge {
+    /// Get the simulated time (in seconds) the entity has been alive.
+    pub fn seconds(&self) -> f32 {
+        self.0 as f32 * FRAME_DELTA_SECONDS
+    }
+
+    /// Get the portion of its lifespan the entity has lived.
+    pub fn life_fraction(&self, traits: &SimEntityTraits) -> f32 {
+        self.seconds() / traits.lifespan
+    }
+
+    /// Whether the entity is old enough to reproduce.
+    pub fn is_mature(&self, traits: &SimEntityTraits) -> bool {
+        self.life_fraction(traits) >= SMITTY_MATURITY
+    }
+
+    /// Get the multiplier applied to the entity's speeds as it grows old,
+    /// falling from 1.0 once its decline starts to `SMITTY_OLD_AGE_SPEED_MULT`
+    /// at the end of its lifespan.
+    pub fn speed_mult(&self, traits: &SimEntityTraits) -> f32 {
+        let decline = ((self.life_fraction(traits) - SMITTY_DECLINE_START)
+            / (1.0 - SMITTY_DECLINE_START))
+            .clamp(0.0, 1.0);
+        1.0 + (SMITTY_OLD_AGE_SPEED_MULT - 1.0) * decline
+    }
+}
+
+/// Component containing where a Smitty came from.
+#[derive(Default, Debug, Copy, Clone, Component)]
+pub struct SimEntityLineage {
+    /// The Smitty that gave birth to this one, or `None` for the founders.
+    pub parent: Option<Entity>,
+    /// The number of ancestors this Smitty has, back to a founder.
+    pub generation: u32,
+}
+
+/// The reasons a Smitty may die.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DeathCause {
+    /// The Smitty wandered past the edge of a world with deadly edges.
+    WorldEdge,
+    /// The Smitty ran out of energy.
+    Starvation,
+    /// The Smitty reached the end of its lifespan.
+    OldAge,
+    /// The Smitty was killed by another.
+    Killed {
+        /// The Smitty that landed the killing blow.
+        by: Entity,
+    },
+}
+
+/// Event sent when a Smitty dies, which removes it at the end of the frame.
+#[derive(Debug, Copy, Clone)]
+pub struct SmittyDeathEvent {
+    /// The entity that died.
+    pub entity: Entity,
+    /// How the entity died.
+    pub cause: DeathCause,
 }
 
 /// A single simulation entity.
@@ -94,32 +714,112 @@ pub struct SmittyBundle {
     pub pos: SimEntityPosRot,
     /// The inputs to the entity's brain.
     pub inputs: SimEntityBrainInputs,
-    /// The entity's desired movement & rotation velocities.
+    /// The raw outputs of the entity's brain.
     pub outputs: SimEntityBrainOutputs,
+    /// The actions the entity decided on.
+    pub actions: SmittyActions,
     /// The entity's traits.
     pub traits: SimEntityTraits,
+    /// The entity's remaining energy.
+    pub energy: SimEntityEnergy,
+    /// How long the entity has been alive.
+    pub age: SimEntityAge,
+    /// The entity's species.
+    pub species: SimEntitySpecies,
+    /// The entity's ancestry.
+    pub lineage: SimEntityLineage,
     /// The entity's sprite
     #[bundle]
     pub sprite: SpriteBundle,
 }
 
-/// System to rotate and move the Smittys by their requested amounts.
+impl SmittyBundle {
+    /// Create a new founding Smitty of a species with the given brain and
+    /// traits at a position, with full energy.
+    pub fn new(
+        brain: SimEntityBrain,
+        traits: SimEntityTraits,
+        species: SimEntitySpecies,
+        pos: Vec2,
+        rot: f32,
+        texture: Handle<Image>,
+    ) -> Self {
+        Self {
+            brain,
+            pos: SimEntityPosRot(pos, rot),
+            inputs: default(),
+            outputs: default(),
+            actions: default(),
+            sprite: SpriteBundle {
+                transform: Transform::from_xyz(pos.x, pos.y, 1.0)
+                    .with_scale(Vec3::splat(SMITTY_SCALE * traits.size)),
+                texture,
+                sprite: Sprite {
+                    color: traits.color(),
+                    custom_size: Some(Vec2::splat(1.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            traits,
+            energy: SimEntityEnergy(SMITTY_MAX_ENERGY),
+            age: default(),
+            species,
+            lineage: default(),
+        }
+    }
+}
+
+/// Extension for adding systems to the simulation schedule's stages, which may
+/// run any number of times per rendered frame.
+pub trait SimulationAppExt {
+    /// Add a system to a stage of the simulation schedule.
+    fn add_simulation_system<Params>(
+        &mut self,
+        stage: impl StageLabel,
+        system: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self;
+}
+
+impl SimulationAppExt for App {
+    fn add_simulation_system<Params>(
+        &mut self,
+        stage: impl StageLabel,
+        system: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self {
+        self.stage(SimulationStage, |schedule: &mut Schedule| {
+            schedule.add_system_to_stage(stage, system)
+        })
+    }
+}
+
+/// System to rotate and move the Smittys by the amounts they decided on.
 ///
-/// This system does not verify that these values are reasonable or allowed!
-fn move_smittys_system(
-    time: Res<Time>,
+/// Smittys are slowed down by the terrain they're standing on and won't move
+/// onto impassable tiles. Smittys whose bodies end up overlapping are then
+/// pushed apart, and feel how hard they're being pressed.
+pub fn move_smittys_system(
+    simworld: Res<SimWorld>,
+    config: Res<SimConfig>,
+    index: Res<SpatialIndex>,
+    mut deaths: EventWriter<SmittyDeathEvent>,
     mut query: Query<(
+        Entity,
         &mut SimEntityPosRot,
-        &SimEntityBrainOutputs,
+        &SmittyActions,
         &SimEntityTraits,
-        &mut Transform,
+        &SimEntityAge,
+        &mut SimEntityBrainInputs,
     )>,
 ) {
     // Loop through the Smittys
-    for (mut pos, request, traits, mut transform) in query.iter_mut() {
+    for (entity, mut pos, actions, traits, age, _) in query.iter_mut() {
+        // Old Smittys slow down
+        let age_mult = age.speed_mult(traits);
+
         // Get the new rotation
-        let rot_req = request.rot_amt * 2.0 - 1.0;
-        let mut new_rot = pos.1 + rot_req * traits.max_rot_speed * time.delta_seconds();
+        let mut new_rot =
+            pos.1 + actions.turn_amt * traits.max_rot_speed * age_mult * FRAME_DELTA_SECONDS;
         // Wrap between 0 and 1 radian
         let rad = 2.0 * PI;
         if new_rot < 0.0 {
@@ -128,42 +828,335 @@ fn move_smittys_system(
             new_rot -= rad;
         }
 
-        // Update the position based on the new rotation
-        pos.0 += Vec2::new(new_rot.cos(), new_rot.sin())
-            * request.move_amt
-            * traits.max_move_speed
-            * time.delta_seconds();
-        // Wrap the position
-        let (w, h) = (WORLD_SIZE.0 as f32, WORLD_SIZE.1 as f32);
-        if pos.0.x < 0.0 {
-            pos.0.x += w;
-        } else if pos.0.x > w {
-            pos.0.x -= w;
-        }
-        if pos.0.y < 0.0 {
-            pos.0.y += h;
-        } else if pos.0.y > h {
-            pos.0.y -= h;
+        // Determine how fast the Smitty can move on its current tile (a Smitty
+        // stuck on an impassable tile, such as one the editor painted over
+        // with rock, may still walk off of it)
+        let terrain_mult = simworld
+            .tile_at(pos.0)
+            .and_then(|tile| tile.tile_type.move_speed_mult(traits.swim_ability))
+            .unwrap_or(1.0);
+
+        // Get the new position based on the new rotation
+        let new_pos = pos.0
+            + Vec2::new(new_rot.cos(), new_rot.sin())
+                * actions.move_amt.clamp(0.0, 1.0)
+                * traits.top_speed(&config.allometry)
+                * (1.0 - traits.armour * SMITTY_ARMOUR_SPEED_PENALTY)
+                * age_mult
+                * terrain_mult
+                * FRAME_DELTA_SECONDS;
+        // Wrap or clamp the position to the world, or kill the Smitty if it
+        // walked off a deadly edge
+        let new_pos = match simworld.bound_pos(new_pos) {
+            Some(new_pos) => new_pos,
+            None => {
+                deaths.send(SmittyDeathEvent {
+                    entity,
+                    cause: DeathCause::WorldEdge,
+                });
+                continue;
+            }
+        };
+
+        // Only move if the destination tile can be entered
+        if simworld.can_step(pos.0, new_pos, traits.swim_ability) {
+            pos.0 = new_pos;
         }
         // Update the rotation
         pos.1 = new_rot;
+    }
+
+    // Find the Smittys whose bodies overlap where they ended up, visiting
+    // them in query order so the pushes add up the same way each run
+    let bodies = query
+        .iter()
+        .map(|(entity, pos, _, traits, ..)| (entity, (pos.0, traits.radius(), traits.mass())))
+        .collect::<Vec<_>>();
+    let lookup = bodies.iter().copied().collect::<HashMap<_, _>>();
+    let mut pushes = HashMap::<Entity, Vec2>::new();
+    let mut pressure = HashMap::<Entity, f32>::new();
+    let mut checked = HashSet::new();
+    for &(entity, (pos, radius, mass)) in bodies.iter() {
+        let search = radius + SMITTY_MAX_RADIUS + SMITTY_COLLISION_MARGIN;
+        for hit in index.within_radius(&simworld, pos, search) {
+            // Only handle each pair once. Smittys born this frame aren't in
+            // the index yet, so they only find their pairs from their own side
+            if hit.entity == entity
+                || !checked.insert((entity.min(hit.entity), entity.max(hit.entity)))
+            {
+                continue;
+            }
+            let (other_pos, other_radius, other_mass) = match lookup.get(&hit.entity) {
+                Some(&body) => body,
+                None => continue,
+            };
+            let delta = simworld.delta(pos, other_pos);
+            let overlap = radius + other_radius - delta.length();
+            if overlap <= 0.0 {
+                continue;
+            }
+            // Smittys right on top of each other are split along the x axis
+            let normal = delta.try_normalize().unwrap_or(Vec2::X);
+            // The lighter Smitty gets pushed farther
+            let share = other_mass / (mass + other_mass);
+            *pushes.entry(entity).or_default() -= normal * overlap * share;
+            *pushes.entry(hit.entity).or_default() += normal * overlap * (1.0 - share);
+            *pressure.entry(entity).or_default() += overlap / radius;
+            *pressure.entry(hit.entity).or_default() += overlap / other_radius;
+        }
+    }
+
+    // Push the overlapping Smittys apart, as long as it doesn't push them off
+    // the world or onto a tile they can't enter
+    for (entity, mut pos, _, traits, _, mut inputs) in query.iter_mut() {
+        inputs.touching = pressure.get(&entity).map_or(0.0, |p| p.tanh());
+        let push = match pushes.get(&entity) {
+            Some(push) => *push,
+            None => continue,
+        };
+        match simworld.bound_pos(pos.0 + push) {
+            Some(new_pos) if simworld.can_step(pos.0, new_pos, traits.swim_ability) => {
+                pos.0 = new_pos
+            }
+            _ => {}
+        }
+    }
+}
 
-        // Update the transform component so the sprite moves
-        let xy = Vec2::new(pos.0.x, pos.0.y);
+/// System to move the Smittys' sprites to their current positions, once per
+/// rendered frame rather than once per simulation tick.
+fn sync_smitty_transforms_system(
+    mut query: Query<(&SimEntityPosRot, &mut Transform), Changed<SimEntityPosRot>>,
+) {
+    for (pos, mut transform) in query.iter_mut() {
         let z = transform.translation.z;
-        transform.translation = Vec3::from((xy, z));
+        transform.translation = pos.0.extend(z);
+    }
+}
+
+/// System to despawn the Smittys that died this frame, leaving their carcasses
+/// behind as meat on the tile they died on.
+fn despawn_dead_smittys_system(
+    mut commands: Commands,
+    mut simworld: ResMut<SimWorld>,
+    mut deaths: EventReader<SmittyDeathEvent>,
+    query: Query<(&SimEntityPosRot, &SimEntityTraits)>,
+) {
+    // An entity could die more than once in a frame, but can only be despawned
+    // once
+    let mut dead = HashSet::new();
+    for death in deaths.iter() {
+        if dead.insert(death.entity) {
+            debug!("{:?} died: {:?}", death.entity, death.cause);
+
+            // Smittys that fell off the edge of the world leave nothing behind
+            if death.cause != DeathCause::WorldEdge {
+                if let Ok((pos, traits)) = query.get(death.entity) {
+                    if let Some(mut tile) = simworld.tile_at_mut(pos.0) {
+                        tile.meat = (tile.meat + SMITTY_CARCASS_MEAT * traits.mass()).min(MAX_MEAT);
+                    }
+                }
+            }
+
+            commands.entity(death.entity).despawn();
+        }
+    }
+}
+
+/// System to let Smittys that want to eat take the plant food and meat on the
+/// tile underfoot, gaining energy according to how well they digest each.
+///
+/// When there isn't enough on a tile for everyone eating from it, it's shared
+/// out in proportion to how much each Smitty wanted, so the order the Smittys
+/// are processed in doesn't matter.
+fn smitty_eat_system(
+    mut simworld: ResMut<SimWorld>,
+    sim_time: Res<SimTime>,
+    mut query: Query<(
+        &SimEntityPosRot,
+        &SimEntityTraits,
+        &SmittyActions,
+        &mut SimEntityEnergy,
+    )>,
+) {
+    let bite = SMITTY_EAT_RATE * FRAME_DELTA_SECONDS;
+    // Don't bother eating what can't be digested
+    let wants = |traits: &SimEntityTraits| {
+        let plant = if traits.plant_efficiency() > 0.0 {
+            bite
+        } else {
+            0.0
+        };
+        let meat = if traits.meat_efficiency() > 0.0 {
+            bite
+        } else {
+            0.0
+        };
+        (plant, meat)
+    };
+
+    // Add up how much plant food and meat is wanted from each tile
+    let mut demand = HashMap::<(usize, usize), (f32, f32)>::new();
+    for (pos, traits, actions, _) in query.iter() {
+        if !actions.eat {
+            continue;
+        }
+        if let Some(tile_pos) = simworld.tile_pos_at(pos.0) {
+            let (plant, meat) = wants(traits);
+            let tile_demand = demand.entry(tile_pos).or_default();
+            tile_demand.0 += plant;
+            tile_demand.1 += meat;
+        }
+    }
+
+    // Take it from the tiles, working out the portion of each bite there's
+    // enough for
+    let mut portions = HashMap::with_capacity(demand.len());
+    for (tile_pos, (plant, meat)) in demand {
+        if let Some(mut tile) = simworld.tile_mut(tile_pos) {
+            let plant_portion = if plant > 0.0 {
+                (tile.food / plant).min(1.0)
+            } else {
+                0.0
+            };
+            let meat_portion = if meat > 0.0 {
+                (tile.meat / meat).min(1.0)
+            } else {
+                0.0
+            };
+            tile.food = (tile.food - plant * plant_portion).max(0.0);
+            tile.meat = (tile.meat - meat * meat_portion).max(0.0);
+            if plant * plant_portion > 0.0 {
+                tile.grazed_frame = sim_time.world_frame;
+            }
+            portions.insert(tile_pos, (plant_portion, meat_portion));
+        }
+    }
+
+    // Then hand it out
+    for (pos, traits, actions, mut energy) in query.iter_mut() {
+        if !actions.eat {
+            continue;
+        }
+        if let Some(&(plant_portion, meat_portion)) = simworld
+            .tile_pos_at(pos.0)
+            .and_then(|tile_pos| portions.get(&tile_pos))
+        {
+            let (plant, meat) = wants(traits);
+            let gained = plant * plant_portion * traits.plant_efficiency()
+                + meat * meat_portion * traits.meat_efficiency();
+            energy.0 = (energy.0 + gained).min(SMITTY_MAX_ENERGY);
+        }
+    }
+}
+
+/// System to age the Smittys by a world frame, and let those that have
+/// reached the end of their lifespan die of old age.
+fn age_smittys_system(
+    mut deaths: EventWriter<SmittyDeathEvent>,
+    mut query: Query<(Entity, &SimEntityTraits, &mut SimEntityAge)>,
+) {
+    for (entity, traits, mut age) in query.iter_mut() {
+        age.0 += 1;
+        if age.life_fraction(traits) >= 1.0 {
+            deaths.send(SmittyDeathEvent {
+                entity,
+                cause: DeathCause::OldAge,
+            });
+        }
+    }
+}
+
+/// System to drain the energy Smittys spend living, moving, seeing, laying
+/// down pheromones and dealing with the terrain they're standing on. Smittys
+/// that run out of energy starve.
+fn drain_smittys_energy_system(
+    simworld: Res<SimWorld>,
+    config: Res<SimConfig>,
+    season: Res<Season>,
+    mut deaths: EventWriter<SmittyDeathEvent>,
+    mut query: Query<(
+        Entity,
+        &SimEntityPosRot,
+        &SimEntityTraits,
+        &SmittyActions,
+        &mut SimEntityEnergy,
+    )>,
+) {
+    for (entity, pos, traits, actions, mut energy) in query.iter_mut() {
+        let terrain_drain = simworld
+            .tile_at(pos.0)
+            .map_or(0.0, |tile| tile.tile_type.energy_drain(traits.swim_ability));
+        let pheromone_drain = actions.pheromones.iter().sum::<f32>() * SMITTY_PHEROMONE_DRAIN;
+        // Moving faster costs much more, so speed isn't free
+        let speed = actions.move_amt.clamp(0.0, 1.0) * traits.top_speed(&config.allometry);
+        let move_drain =
+            SMITTY_MOVE_ENERGY_DRAIN * traits.mass() * (speed / SMITTY_MAX_MOVE_SPEED).powi(2);
+        let drain = traits.metabolic_drain(&config.allometry)
+            + move_drain
+            + traits.vision_energy_drain()
+            + pheromone_drain
+            + season.cold_energy_drain(traits)
+            + terrain_drain;
+        energy.0 = (energy.0 - drain * FRAME_DELTA_SECONDS).max(0.0);
+
+        if energy.0 <= 0.0 {
+            deaths.send(SmittyDeathEvent {
+                entity,
+                cause: DeathCause::Starvation,
+            });
+        }
     }
 }
 
 /// System to collect information for neural network inputs.
-fn neural_network_collect_system() {
+fn neural_network_collect_system(
+    simworld: Res<SimWorld>,
+    index: Res<SpatialIndex>,
+    season: Res<Season>,
+    mut query: Query<(
+        Entity,
+        &SimEntityPosRot,
+        &SimEntityEnergy,
+        &SimEntityTraits,
+        &SimEntityAge,
+        &mut SimEntityBrainInputs,
+    )>,
+) {
     debug!("collecting data");
+
+    for (entity, pos, energy, traits, age, mut inputs) in query.iter_mut() {
+        inputs.energy = energy.0 / SMITTY_MAX_ENERGY;
+        inputs.age = age.life_fraction(traits).min(1.0);
+        inputs.temperature = season.temperature();
+        inputs.season = season.trend();
+        inputs.tile_food = simworld
+            .tile_at(pos.0)
+            .filter(|tile| tile.max_food > 0.0)
+            .map_or(0.0, |tile| tile.food / tile.max_food);
+
+        // Look for the nearest other Smitty (the nearest is probably this one)
+        let nearest = index
+            .nearest(&simworld, pos.0, 2)
+            .into_iter()
+            .find(|hit| hit.entity != entity && hit.distance <= traits.vision_range);
+        if let Some(hit) = nearest {
+            let delta = simworld.delta(pos.0, hit.pos);
+            // Wrap the relative angle between -PI and PI
+            let bearing = (delta.y.atan2(delta.x) - pos.1 + PI).rem_euclid(2.0 * PI) - PI;
+            inputs.nearest_proximity = 1.0 - hit.distance / traits.vision_range;
+            inputs.nearest_bearing = bearing / PI;
+        } else {
+            inputs.nearest_proximity = 0.0;
+            inputs.nearest_bearing = 0.0;
+        }
+    }
 }
 
 /// Perform the network update (feed-forward the previously collected inputs.
 fn neural_network_update_system(
     mut brains: Query<(
-        &SimEntityBrain,
+        &mut SimEntityBrain,
         &SimEntityBrainInputs,
         &mut SimEntityBrainOutputs,
     )>,
@@ -171,22 +1164,175 @@ fn neural_network_update_system(
     debug!("updating neural networks");
 
     // Loop through all the brains in the world
-    for (brain, inputs, mut outputs) in brains.iter_mut() {
-        // Feed-forward
-        let output_results = brain
+    for (mut brain, inputs, mut outputs) in brains.iter_mut() {
+        // Feed-forward, keeping every layer's values around for inspection
+        brain.activations = brain
             .network
-            .run(NNActivation::Sigmoid, &[0.5, 0.5])
+            .run_all(NNActivation::Sigmoid, &inputs.to_array())
             .unwrap();
+        let output_results = brain.activations.last().unwrap();
 
         // Update the output
-        outputs.move_amt = output_results[0];
-        outputs.rot_amt = output_results[1];
+        outputs.0.copy_from_slice(output_results);
+    }
+}
+
+/// System to let Smittys that want to attack hit the nearest Smitty within
+/// reach, taking some of its energy.
+///
+/// Attacks are resolved one at a time in order of the attackers' entity IDs, so
+/// a Smitty killed by an earlier attack doesn't get to attack this tick.
+fn smitty_attack_system(
+    simworld: Res<SimWorld>,
+    config: Res<SimConfig>,
+    index: Res<SpatialIndex>,
+    mut deaths: EventWriter<SmittyDeathEvent>,
+    mut query: Query<(
+        Entity,
+        &SimEntityPosRot,
+        &SmittyActions,
+        &SimEntityTraits,
+        &mut SimEntityEnergy,
+    )>,
+) {
+    // Find who wants to attack whom
+    let mut attacks = query
+        .iter()
+        .filter(|(_, _, actions, _, _)| actions.attack)
+        .filter_map(|(attacker, pos, _, _, _)| {
+            index
+                .nearest(&simworld, pos.0, 2)
+                .into_iter()
+                .find(|hit| hit.entity != attacker && hit.distance <= SMITTY_ATTACK_RANGE)
+                .map(|hit| (attacker, hit.entity))
+        })
+        .collect::<Vec<_>>();
+    attacks.sort();
+
+    for (attacker, victim) in attacks {
+        // The dead don't fight back
+        let attacker_strength = match query.get(attacker) {
+            Ok((_, _, _, traits, energy)) if energy.0 > 0.0 => traits.strength(&config.allometry),
+            _ => continue,
+        };
+
+        // Hurt the victim
+        let taken = match query.get_mut(victim) {
+            Ok((_, _, _, traits, mut energy)) if energy.0 > 0.0 => {
+                let damage = SMITTY_ATTACK_DAMAGE
+                    * (attacker_strength / traits.strength(&config.allometry))
+                    * (1.0 - traits.armour.clamp(0.0, 1.0));
+                let taken = damage.min(energy.0);
+                energy.0 -= taken;
+                if energy.0 <= 0.0 {
+                    deaths.send(SmittyDeathEvent {
+                        entity: victim,
+                        cause: DeathCause::Killed { by: attacker },
+                    });
+                }
+                taken
+            }
+            _ => 0.0,
+        };
+
+        // Feed the attacker, minus what it cost to attack
+        if let Ok((_, _, _, _, mut energy)) = query.get_mut(attacker) {
+            energy.0 = (energy.0 + taken * SMITTY_ATTACK_EFFICIENCY - SMITTY_ATTACK_COST)
+                .clamp(0.0, SMITTY_MAX_ENERGY);
+            if energy.0 <= 0.0 {
+                deaths.send(SmittyDeathEvent {
+                    entity: attacker,
+                    cause: DeathCause::Starvation,
+                });
+            }
+        }
     }
 }
 
-/// Update the entity based on its neural network outputs.
-fn neural_network_perform_system() {
+/// System to turn the Smittys' brain outputs into the actions they'll take
+/// until their next neural update.
+fn neural_network_perform_system(mut query: Query<(&SimEntityBrainOutputs, &mut SmittyActions)>) {
     debug!("executing network outputs");
+
+    for (outputs, mut actions) in query.iter_mut() {
+        *actions = SmittyActions::from_outputs(outputs);
+    }
+}
+
+/// System to let Smittys that want to reproduce, and are old enough and have
+/// the energy to, give birth to a child with a mutated brain and traits just
+/// behind them.
+///
+/// Births happen in order of the parents' entity IDs, so new species are
+/// numbered the same way however the query is ordered.
+fn smitty_reproduce_system(
+    mut commands: Commands,
+    simworld: Res<SimWorld>,
+    mut species_registry: ResMut<SpeciesRegistry>,
+    mut rng: ResMut<SimRng>,
+    mut query: Query<(
+        Entity,
+        &SimEntityPosRot,
+        &SimEntityBrain,
+        &SimEntityTraits,
+        &SimEntitySpecies,
+        &SimEntityLineage,
+        &SmittyActions,
+        &SimEntityAge,
+        &mut SimEntityEnergy,
+        &Handle<Image>,
+    )>,
+) {
+    let mut parents = query
+        .iter()
+        .filter(|(_, _, _, traits, .., actions, age, energy, _)| {
+            actions.reproduce && age.is_mature(traits) && energy.0 >= SMITTY_REPRODUCE_MIN_ENERGY
+        })
+        .map(|(parent, ..)| parent)
+        .collect::<Vec<_>>();
+    parents.sort();
+
+    let rng = &mut rng.0;
+    for parent in parents {
+        let (_, pos, brain, traits, species, lineage, _, _, mut energy, texture) =
+            query.get_mut(parent).unwrap();
+        let child_traits = traits.mutated(rng);
+
+        // The child is born behind its parent, or on top of it if there's no
+        // room, as long as it can stand there
+        let behind =
+            pos.0 - Vec2::new(pos.1.cos(), pos.1.sin()) * SMITTY_BIRTH_DISTANCE * traits.size;
+        let passable = |pos: Vec2| simworld.is_passable(pos, child_traits.swim_ability);
+        let child_pos = match simworld.bound_pos(behind) {
+            Some(behind) if passable(behind) => behind,
+            _ if passable(pos.0) => pos.0,
+            _ => continue,
+        };
+
+        // Children too different from their parents found their own species
+        let child_species = if child_traits.distance(traits) > SPECIATION_DISTANCE {
+            species_registry.new_species()
+        } else {
+            *species
+        };
+
+        let mut child = SmittyBundle::new(
+            brain.mutated(rng),
+            child_traits,
+            child_species,
+            child_pos,
+            rng.gen_range(0.0..2.0 * PI),
+            texture.clone(),
+        );
+        child.energy.0 = SMITTY_CHILD_ENERGY;
+        child.lineage = SimEntityLineage {
+            parent: Some(parent),
+            generation: lineage.generation + 1,
+        };
+        commands.spawn(child);
+
+        energy.0 -= SMITTY_CHILD_ENERGY + SMITTY_REPRODUCE_COST;
+    }
 }
 
 /// The state of the simulation (i.e. whether it is running, running for a
@@ -205,14 +1351,30 @@ pub enum SimulationMode {
     /// The engine will perform one tick and switch the `SimulationState` back
     /// to `Stop`.
     Single,
-    /* /// The engine will perform however many ticks are between neural updates
-       /// and switch the `SimulationState` back to `Stop` afterwards.
-    Brain, */
+    /// The engine will perform however many ticks are between neural updates
+    /// and switch the `SimulationState` back to `Stop` afterwards.
+    Brain,
+    /// The engine will perform ticks until the world frame count reaches the
+    /// given frame and switch the `SimulationState` back to `Stop` afterwards.
+    UntilFrame(u32),
     /// The simulation will run until the `SimulationState` is changed to
     /// `Stop`.
     Auto,
 }
 
+impl SimulationMode {
+    /// Check whether the tick that just finished should be the last one before
+    /// the simulation stops.
+    pub fn is_done(&self, sim_time: &SimTime) -> bool {
+        match *self {
+            Self::Single => true,
+            Self::Brain => sim_time.is_neural_tick_frame,
+            Self::UntilFrame(frame) => sim_time.world_frame >= frame,
+            Self::Auto => false,
+        }
+    }
+}
+
 #[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Resource)]
 pub struct SimTime {
     /// The current world execution frame count.
@@ -228,7 +1390,7 @@ pub struct SimTime {
 
 /// System to update the simulation time resource.
 /// When this system is called, the frame is ticked.
-fn update_simulation_time_system(mut sim_time: ResMut<SimTime>) {
+pub fn update_simulation_time_system(mut sim_time: ResMut<SimTime>) {
     // Increment the frame and determine how many frames have passed since the
     // last neural update
     let frame = sim_time.world_frame + 1;
@@ -243,9 +1405,134 @@ fn update_simulation_time_system(mut sim_time: ResMut<SimTime>) {
     }
 }
 
+/// System to stop the simulation once the current `SimulationMode` has run
+/// its course, switching back to `Auto` for the next time it's started.
+fn update_simulation_mode_system(
+    mut commands: Commands,
+    sim_time: Res<SimTime>,
+    mode: Res<CurrentState<SimulationMode>>,
+) {
+    if mode.0.is_done(&sim_time) {
+        debug!("finished running in {:?} mode", mode.0);
+        commands.insert_resource(NextState(SimulationState::Stop));
+        commands.insert_resource(NextState(SimulationMode::Auto));
+    }
+}
+
+/// Resource controlling how fast the simulation runs compared to real time.
+#[derive(Debug, Resource)]
+pub struct SimSpeed {
+    /// How many times faster than real time the simulation should run, or
+    /// `None` to run as many ticks as fit in the `SIM_TICK_BUDGET`.
+    pub multiplier: Option<f32>,
+    /// The simulated time owed that hasn't been run yet.
+    accumulator: f32,
+    /// The ticks left to run this rendered frame.
+    remaining_ticks: u32,
+    /// When the first tick of this rendered frame started.
+    frame_start: Option<Instant>,
+    /// The ticks run since the ticks per second was last measured.
+    window_ticks: u32,
+    /// When the ticks per second was last measured, in seconds since startup.
+    window_start: f32,
+    /// The number of ticks run per real second, last time it was measured.
+    ticks_per_second: f32,
+}
+
+impl SimSpeed {
+    /// Create a speed that runs as many ticks as fit in each frame's budget.
+    pub fn max() -> Self {
+        Self {
+            multiplier: None,
+            ..default()
+        }
+    }
+
+    /// Get the number of simulation ticks that ran in the last real second.
+    pub fn ticks_per_second(&self) -> f32 {
+        self.ticks_per_second
+    }
+}
+
+impl Default for SimSpeed {
+    fn default() -> Self {
+        Self {
+            multiplier: Some(1.0),
+            accumulator: 0.0,
+            remaining_ticks: 0,
+            frame_start: None,
+            window_ticks: 0,
+            window_start: 0.0,
+            ticks_per_second: 0.0,
+        }
+    }
+}
+
+/// System to work out how many simulation ticks to run this rendered frame.
+fn plan_simulation_ticks_system(
+    time: Res<Time>,
+    sim_state: Res<CurrentState<SimulationState>>,
+    mut speed: ResMut<SimSpeed>,
+) {
+    speed.frame_start = None;
+
+    // Measure the achieved ticks per second
+    let now = time.elapsed_seconds();
+    if now - speed.window_start >= 1.0 {
+        speed.ticks_per_second = speed.window_ticks as f32 / (now - speed.window_start);
+        speed.window_ticks = 0;
+        speed.window_start = now;
+    }
+
+    // Don't save up time while stopped
+    if sim_state.0 != SimulationState::Run {
+        speed.accumulator = 0.0;
+        speed.remaining_ticks = 0;
+        return;
+    }
+
+    speed.remaining_ticks = match speed.multiplier {
+        Some(multiplier) => {
+            let multiplier = multiplier.clamp(SIM_MIN_SPEED, SIM_MAX_SPEED);
+            speed.accumulator += time.delta_seconds() * multiplier;
+            let ticks = (speed.accumulator / FRAME_DELTA_SECONDS).floor();
+            speed.accumulator -= ticks * FRAME_DELTA_SECONDS;
+            ticks as u32
+        }
+        None => u32::MAX,
+    };
+}
+
+/// Run criteria for the simulation schedule that keeps running ticks until
+/// this frame's ticks are done, the simulation is stopping, or the frame's
+/// time budget runs out.
+fn simulation_tick_criteria(
+    sim_state: Res<CurrentState<SimulationState>>,
+    next_state: Option<Res<NextState<SimulationState>>>,
+    mut speed: ResMut<SimSpeed>,
+) -> ShouldRun {
+    let stopping = next_state.map_or(false, |next| next.0 == SimulationState::Stop);
+    if sim_state.0 != SimulationState::Run || stopping || speed.remaining_ticks == 0 {
+        return ShouldRun::No;
+    }
+
+    // Drop whatever didn't fit in the budget rather than falling further and
+    // further behind
+    let start = *speed.frame_start.get_or_insert_with(Instant::now);
+    if start.elapsed() >= SIM_TICK_BUDGET {
+        speed.remaining_ticks = 0;
+        speed.accumulator = 0.0;
+        return ShouldRun::No;
+    }
+
+    speed.remaining_ticks -= 1;
+    speed.window_ticks += 1;
+    ShouldRun::YesAndCheckAgain
+}
+
 /// Simple system to check whether the simulation should run a neural network
 /// update this frame.
-fn is_neural_update_frame_system(sim_time: Res<SimTime>) -> bool {
+pub fn is_neural_update_frame_system(sim_time: Res<SimTime>) -> bool {
     sim_time.is_neural_tick_frame
 }
 
@@ -254,29 +1541,30 @@ pub struct NetworkEcsPlugin;
 
 impl Plugin for NetworkEcsPlugin {
     fn build(&self, app: &mut App) {
-        app
-            // Add resources
-            .init_resource::<SimTime>()
-            // Add states
-            .add_loopless_state(SimulationState::Stop)
-            .add_loopless_state(SimulationMode::Single)
-            // Add the frame stages
-            .add_stage_before(
-                CoreStage::Update,
+        // The simulation schedule, with the frame stages and the brain stages
+        let mut sim_schedule = Schedule::default().with_run_criteria(simulation_tick_criteria);
+        sim_schedule
+            .add_stage(FrameUpdateStage::UpdateTiming, SystemStage::parallel())
+            .add_stage_after(
                 FrameUpdateStage::UpdateTiming,
+                FrameUpdateStage::UpdateNeural,
                 SystemStage::parallel(),
             )
             .add_stage_after(
-                FrameUpdateStage::UpdateTiming,
                 FrameUpdateStage::UpdateNeural,
+                FrameUpdateStage::UpdateEntities,
                 SystemStage::parallel(),
             )
             .add_stage_after(
-                FrameUpdateStage::UpdateNeural,
                 FrameUpdateStage::UpdateEntities,
+                FrameUpdateStage::Cleanup,
+                SystemStage::parallel(),
+            )
+            .add_stage_after(
+                FrameUpdateStage::Cleanup,
+                FrameUpdateStage::UpdateIndex,
                 SystemStage::parallel(),
             )
-            // Add the brain stages
             .add_stage_after(
                 FrameUpdateStage::UpdateNeural,
                 NeuralUpdateStage::Collect,
@@ -291,34 +1579,98 @@ impl Plugin for NetworkEcsPlugin {
                 NeuralUpdateStage::Update,
                 NeuralUpdateStage::Perform,
                 SystemStage::parallel(),
-            )
+            );
+
+        app
+            // Add resources
+            .init_resource::<SimTime>()
+            .init_resource::<SimSpeed>()
+            .init_resource::<SpeciesRegistry>()
+            .init_resource::<SimRng>()
+            // Add events
+            .add_event::<SmittyDeathEvent>()
+            // Add states
+            .add_loopless_state(SimulationState::Stop)
+            .add_loopless_state(SimulationMode::Single)
+            // Add the simulation schedule and plan its ticks each frame
+            .add_stage_before(CoreStage::Update, SimulationStage, sim_schedule)
+            .add_system_to_stage(CoreStage::PreUpdate, plan_simulation_ticks_system)
             // Add the neural update systems
-            .add_system_to_stage(
+            .add_simulation_system(
                 NeuralUpdateStage::Collect,
                 neural_network_collect_system
                     .run_in_state(SimulationState::Run)
                     .run_if(is_neural_update_frame_system),
             )
-            .add_system_to_stage(
+            .add_simulation_system(
                 NeuralUpdateStage::Update,
                 neural_network_update_system
                     .run_in_state(SimulationState::Run)
                     .run_if(is_neural_update_frame_system),
             )
-            .add_system_to_stage(
+            .add_simulation_system(
                 NeuralUpdateStage::Perform,
                 neural_network_perform_system
                     .run_in_state(SimulationState::Run)
                     .run_if(is_neural_update_frame_system),
             )
-            // Add the per-frame systems for when the simulation is running
-            .add_system_set_to_stage(
+            // Act on the new actions once they've been decided
+            .add_simulation_system(
+                NeuralUpdateStage::Perform,
+                smitty_attack_system
+                    .run_in_state(SimulationState::Run)
+                    .run_if(is_neural_update_frame_system)
+                    .after(neural_network_perform_system),
+            )
+            .add_simulation_system(
+                NeuralUpdateStage::Perform,
+                smitty_reproduce_system
+                    .run_in_state(SimulationState::Run)
+                    .run_if(is_neural_update_frame_system)
+                    .after(smitty_attack_system),
+            )
+            // Tick the frame first so the neural stages know whether to run
+            .add_simulation_system(
+                FrameUpdateStage::UpdateTiming,
+                update_simulation_time_system.run_in_state(SimulationState::Run),
+            )
+            // Add the per-frame systems for when the simulation is running, in
+            // a fixed order so that a run can be reproduced from its seed
+            .add_simulation_system(
                 FrameUpdateStage::UpdateEntities,
-                ConditionSet::new()
+                move_smittys_system
                     .run_in_state(SimulationState::Run)
-                    .with_system(move_smittys_system)
-                    .with_system(update_simulation_time_system)
-                    .into(),
+                    .after(update_food_system),
+            )
+            .add_simulation_system(
+                FrameUpdateStage::UpdateEntities,
+                age_smittys_system
+                    .run_in_state(SimulationState::Run)
+                    .after(move_smittys_system),
+            )
+            .add_simulation_system(
+                FrameUpdateStage::UpdateEntities,
+                drain_smittys_energy_system
+                    .run_in_state(SimulationState::Run)
+                    .after(age_smittys_system),
+            )
+            .add_simulation_system(
+                FrameUpdateStage::UpdateEntities,
+                smitty_eat_system
+                    .run_in_state(SimulationState::Run)
+                    .after(drain_smittys_energy_system),
+            )
+            // Stop after stepping if the mode says so
+            .add_simulation_system(
+                FrameUpdateStage::Cleanup,
+                update_simulation_mode_system.run_in_state(SimulationState::Run),
+            )
+            // Remove the dead
+            .add_simulation_system(FrameUpdateStage::Cleanup, despawn_dead_smittys_system)
+            // Move the sprites once all of this frame's ticks are done
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                sync_smitty_transforms_system.before(TransformSystem::TransformPropagate),
             );
     }
 }
@@ -0,0 +1,358 @@
+//! Tools for painting the world's tiles and placing Smittys by hand.
+
+use crate::{
+    camera::FitCameraEvent,
+    ecs::{
+        SimEntityBrain, SimEntityPosRot, SimEntityTraits, SimRng, SmittyBundle, SpeciesRegistry,
+    },
+    gui::CursorState,
+    map::{load_map, save_map},
+    simworld::{SimTileType, SimWorld},
+    spatial::SpatialIndex,
+};
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use rand::Rng;
+use std::{collections::VecDeque, f32::consts::PI, path::Path};
+
+/// The map file the editor saves to and loads from until another is entered.
+const DEFAULT_MAP_PATH: &str = "world.map";
+
+/// Plugin that lets the user edit the world with the mouse.
+pub struct TileEditorPlugin;
+
+impl Plugin for TileEditorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // Add the editor settings and map file events
+            .init_resource::<TileEditor>()
+            .add_event::<SaveMapEvent>()
+            .add_event::<LoadMapEvent>()
+            // Add the editing systems
+            .add_system(edit_world_system)
+            .add_system(save_map_system)
+            .add_system(load_map_system);
+    }
+}
+
+/// The tools available in the tile editor.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EditorTool {
+    /// Paint the tile type with the brush.
+    PaintType,
+    /// Set the food with the brush.
+    SetFood,
+    /// Set the maximum food with the brush.
+    SetMaxFood,
+    /// Fill the region of same-typed tiles under the cursor with the tile type.
+    FloodFill,
+    /// Place a random Smitty under the cursor.
+    PlaceSmitty,
+    /// Delete the Smitty under the cursor.
+    DeleteSmitty,
+}
+
+/// Resource containing the tile editor's settings.
+#[derive(Resource)]
+pub struct TileEditor {
+    /// Whether clicking the world edits it rather than selecting Smittys.
+    pub enabled: bool,
+    /// The tool used when clicking the world.
+    pub tool: EditorTool,
+    /// The radius (in tiles) of the brush.
+    pub brush_radius: f32,
+    /// The tile type painted and filled.
+    pub tile_type: SimTileType,
+    /// The food set by the food brush.
+    pub food: f32,
+    /// The maximum food set by the maximum food brush.
+    pub max_food: f32,
+    /// The map file to save to and load from.
+    pub map_path: String,
+    /// The result of the last save or load, to show the user.
+    pub status: Option<String>,
+}
+
+impl Default for TileEditor {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tool: EditorTool::PaintType,
+            brush_radius: 0.0,
+            tile_type: SimTileType::Land,
+            food: 1.0,
+            max_food: 1.0,
+            map_path: DEFAULT_MAP_PATH.to_owned(),
+            status: None,
+        }
+    }
+}
+
+/// Event sent to save the world to the editor's map file.
+pub struct SaveMapEvent;
+
+/// Event sent to replace the world with the editor's map file.
+pub struct LoadMapEvent;
+
+/// System to apply the current tool where the user clicks (or drags, for
+/// brushes) on the world.
+#[allow(clippy::too_many_arguments)]
+fn edit_world_system(
+    mut commands: Commands,
+    editor: Res<TileEditor>,
+    mouse: Res<Input<MouseButton>>,
+    cursor_state: Res<CursorState>,
+    mut simworld: ResMut<SimWorld>,
+    index: Res<SpatialIndex>,
+    mut species: ResMut<SpeciesRegistry>,
+    mut rng: ResMut<SimRng>,
+    assets: Res<AssetServer>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    // Ignore clicks meant for the UI
+    if !editor.enabled || egui_context.ctx_mut().is_pointer_over_area() {
+        return;
+    }
+    let tile_pos = match cursor_state.tile_pos {
+        Some(tile_pos) => tile_pos,
+        None => return,
+    };
+
+    match editor.tool {
+        // Brushes paint while the button is held
+        EditorTool::PaintType | EditorTool::SetFood | EditorTool::SetMaxFood
+            if mouse.pressed(MouseButton::Left) =>
+        {
+            for pos in brush_tiles(&simworld, cursor_state.world_pos, editor.brush_radius) {
+                let mut tile = simworld.tile_mut(pos).unwrap();
+                match editor.tool {
+                    EditorTool::PaintType => {
+                        tile.tile_type = editor.tile_type;
+                        // Nothing grows on rocks
+                        if editor.tile_type == SimTileType::Rock {
+                            tile.food = 0.0;
+                            tile.max_food = 0.0;
+                        }
+                    }
+                    EditorTool::SetFood => tile.food = editor.food.min(tile.max_food),
+                    _ => {
+                        tile.max_food = editor.max_food;
+                        tile.food = tile.food.min(tile.max_food);
+                    }
+                }
+            }
+        }
+        // Everything else happens once per click
+        _ if !mouse.just_pressed(MouseButton::Left) => {}
+        EditorTool::FloodFill => flood_fill(&mut simworld, tile_pos, editor.tile_type),
+        EditorTool::PlaceSmitty => {
+            let rng = &mut rng.0;
+            let traits = SimEntityTraits::random(rng);
+            if simworld.is_passable(cursor_state.world_pos, traits.swim_ability) {
+                commands.spawn(SmittyBundle::new(
+                    SimEntityBrain::random(rng),
+                    traits,
+                    species.new_species(),
+                    cursor_state.world_pos,
+                    rng.gen_range(0.0..2.0 * PI),
+                    assets.load("smitty.png"),
+                ));
+            }
+        }
+        EditorTool::DeleteSmitty => {
+            let nearest = index.nearest(&simworld, cursor_state.world_pos, 1);
+            if let Some(hit) = nearest.first() {
+                if hit.distance <= editor.brush_radius.max(0.5) {
+                    commands.entity(hit.entity).despawn();
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Get the positions of the tiles under a brush, which is always at least the
+/// tile under the cursor.
+fn brush_tiles(simworld: &SimWorld, center: Vec2, radius: f32) -> Vec<(usize, usize)> {
+    let (w, h) = simworld.size();
+    let min = (center - radius).floor().max(Vec2::ZERO).as_uvec2();
+    let max = (center + radius)
+        .floor()
+        .min(Vec2::new(w as f32 - 1.0, h as f32 - 1.0))
+        .as_uvec2();
+    let cursor_tile = center.floor();
+
+    let mut tiles = Vec::new();
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let tile = Vec2::new(x as f32, y as f32);
+            if tile == cursor_tile || (tile + 0.5).distance(center) <= radius {
+                tiles.push((x as usize, y as usize));
+            }
+        }
+    }
+    tiles
+}
+
+/// Change the type of every tile connected to the given one by tiles of the
+/// same type.
+fn flood_fill(simworld: &mut SimWorld, start: (usize, usize), tile_type: SimTileType) {
+    let target = match simworld.tile(start) {
+        Some(tile) if tile.tile_type != tile_type => tile.tile_type,
+        _ => return,
+    };
+
+    let (w, h) = simworld.size();
+    let mut queue = VecDeque::from([start]);
+    while let Some((x, y)) = queue.pop_front() {
+        match simworld.tile((x, y)) {
+            Some(tile) if tile.tile_type == target => {}
+            _ => continue,
+        }
+        let mut tile = simworld.tile_mut((x, y)).unwrap();
+        tile.tile_type = tile_type;
+        if tile_type == SimTileType::Rock {
+            tile.food = 0.0;
+            tile.max_food = 0.0;
+        }
+
+        if x > 0 {
+            queue.push_back((x - 1, y));
+        }
+        if x + 1 < w {
+            queue.push_back((x + 1, y));
+        }
+        if y > 0 {
+            queue.push_back((x, y - 1));
+        }
+        if y + 1 < h {
+            queue.push_back((x, y + 1));
+        }
+    }
+}
+
+/// System to save the world to the editor's map file when requested.
+fn save_map_system(
+    mut events: EventReader<SaveMapEvent>,
+    mut editor: ResMut<TileEditor>,
+    simworld: Res<SimWorld>,
+) {
+    if events.iter().last().is_none() {
+        return;
+    }
+
+    let status = match save_map(&simworld, Path::new(&editor.map_path)) {
+        Ok(()) => format!("saved map to {}", editor.map_path),
+        Err(err) => format!("failed to save map: {}", err),
+    };
+    info!("{}", status);
+    editor.status = Some(status);
+}
+
+/// System to replace the world with the editor's map file when requested,
+/// removing any Smittys that no longer have anywhere to stand.
+fn load_map_system(
+    mut commands: Commands,
+    mut events: EventReader<LoadMapEvent>,
+    mut editor: ResMut<TileEditor>,
+    mut simworld: ResMut<SimWorld>,
+    mut fit_camera: EventWriter<FitCameraEvent>,
+    smittys: Query<(Entity, &SimEntityPosRot, &SimEntityTraits)>,
+) {
+    if events.iter().last().is_none() {
+        return;
+    }
+
+    let status = match load_map(Path::new(&editor.map_path)) {
+        Ok(map) => {
+            simworld.replace_with(map);
+            for (entity, pos, traits) in smittys.iter() {
+                if !simworld.is_passable(pos.0, traits.swim_ability) {
+                    commands.entity(entity).despawn();
+                }
+            }
+            fit_camera.send(FitCameraEvent);
+            format!("loaded map from {}", editor.map_path)
+        }
+        Err(err) => format!("failed to load map: {}", err),
+    };
+    info!("{}", status);
+    editor.status = Some(status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simworld::SimTile;
+
+    /// A 5x5 world with a ring of water around a land tile in its middle, and
+    /// land everywhere else.
+    fn ring_world() -> SimWorld {
+        let mut simworld = SimWorld::from_tiles((5, 5), vec![SimTile::default(); 25]);
+        for y in 1..=3 {
+            for x in 1..=3 {
+                if (x, y) != (2, 2) {
+                    simworld.tile_mut((x, y)).unwrap().tile_type = SimTileType::Water;
+                }
+            }
+        }
+        simworld
+    }
+
+    #[test]
+    fn brush_tiles_within_radius() {
+        let simworld = ring_world();
+        let mut tiles = brush_tiles(&simworld, Vec2::new(2.5, 2.5), 1.0);
+        tiles.sort_unstable();
+        assert_eq!(tiles, [(1, 2), (2, 1), (2, 2), (2, 3), (3, 2)]);
+    }
+
+    #[test]
+    fn brush_tiles_always_has_the_cursor_tile() {
+        let simworld = ring_world();
+        assert_eq!(brush_tiles(&simworld, Vec2::new(3.9, 0.1), 0.0), [(3, 0)]);
+    }
+
+    #[test]
+    fn brush_tiles_stop_at_the_edges() {
+        let simworld = ring_world();
+        let mut tiles = brush_tiles(&simworld, Vec2::new(0.5, 4.5), 1.0);
+        tiles.sort_unstable();
+        assert_eq!(tiles, [(0, 3), (0, 4), (1, 4)]);
+    }
+
+    #[test]
+    fn flood_fill_stays_within_connected_tiles() {
+        let mut simworld = ring_world();
+        flood_fill(&mut simworld, (0, 0), SimTileType::Rock);
+        for y in 0..5 {
+            for x in 0..5 {
+                let tile = simworld.tile((x, y)).unwrap();
+                let expected = match (x, y) {
+                    (2, 2) => SimTileType::Land,
+                    (1..=3, 1..=3) => SimTileType::Water,
+                    _ => SimTileType::Rock,
+                };
+                assert_eq!(tile.tile_type, expected, "tile ({}, {})", x, y);
+            }
+        }
+
+        flood_fill(&mut simworld, (1, 1), SimTileType::Land);
+        let land = simworld
+            .tiles()
+            .filter(|tile| tile.tile_type == SimTileType::Land)
+            .count();
+        assert_eq!(land, 9);
+    }
+
+    #[test]
+    fn flood_fill_with_the_same_type_does_nothing() {
+        let mut simworld = ring_world();
+        let tracker = simworld.track_dirty_tiles();
+        simworld.take_dirty_tiles(tracker);
+        flood_fill(&mut simworld, (2, 2), SimTileType::Land);
+        assert!(simworld.take_dirty_tiles(tracker).is_empty());
+        flood_fill(&mut simworld, (5, 0), SimTileType::Rock);
+        assert!(simworld.take_dirty_tiles(tracker).is_empty());
+    }
+}
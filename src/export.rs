@@ -0,0 +1,569 @@
+//! Export of statistics to files for analysis outside of the simulator.
+
+use crate::{
+    config::{ExportFormat, SimConfig},
+    ecs::{
+        is_neural_update_frame_system, DeathCause, FrameUpdateStage, SimEntityAge, SimEntityEnergy,
+        SimEntityLineage, SimEntityPosRot, SimEntitySpecies, SimEntityTraits, SimTime,
+        SimulationAppExt, SimulationState, SmittyDeathEvent,
+    },
+    season::Season,
+    simworld::{SimTileType, SimWorld},
+    stats::{record_stats_system, SimStats},
+};
+use bevy::{prelude::*, utils::HashSet};
+use iyes_loopless::prelude::*;
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Possible errors returned when exporting statistics.
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("failed to write export file: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A single value in an exported record.
+#[derive(Debug, Clone)]
+pub enum ExportValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    Str(String),
+}
+
+impl From<bool> for ExportValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<u32> for ExportValue {
+    fn from(value: u32) -> Self {
+        Self::Int(value as i64)
+    }
+}
+
+impl From<i32> for ExportValue {
+    fn from(value: i32) -> Self {
+        Self::Int(value as i64)
+    }
+}
+
+impl From<f32> for ExportValue {
+    fn from(value: f32) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<&str> for ExportValue {
+    fn from(value: &str) -> Self {
+        Self::Str(value.to_owned())
+    }
+}
+
+impl From<Entity> for ExportValue {
+    fn from(value: Entity) -> Self {
+        Self::Int(value.to_bits() as i64)
+    }
+}
+
+impl<T: Into<ExportValue>> From<Option<T>> for ExportValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+/// A row of named values to export.
+pub type ExportRecord = Vec<(&'static str, ExportValue)>;
+
+/// A single exported file, written in one of the export formats.
+struct ExportFile {
+    writer: BufWriter<File>,
+    format: ExportFormat,
+    /// Whether the CSV header row has been written yet.
+    wrote_header: bool,
+}
+
+impl ExportFile {
+    /// Create the file with the given name (without an extension) in a
+    /// directory, starting it with the run's provenance.
+    fn create(
+        dir: &Path,
+        name: &str,
+        format: ExportFormat,
+        provenance: &ExportRecord,
+    ) -> Result<Self, ExportError> {
+        let path = dir.join(name).with_extension(format.extension());
+        let mut file = Self {
+            writer: BufWriter::new(File::create(path)?),
+            format,
+            wrote_header: false,
+        };
+
+        // CSV readers can skip comments, and JSON Lines readers can filter out
+        // the record without the usual fields
+        match format {
+            ExportFormat::Csv => {
+                let mut line = String::from("#");
+                for (key, value) in provenance {
+                    let _ = write!(line, " {}={}", key, csv_value(value));
+                }
+                writeln!(file.writer, "{}", line)?;
+            }
+            ExportFormat::JsonLines => {
+                writeln!(
+                    file.writer,
+                    "{{\"provenance\":{}}}",
+                    json_object(provenance)
+                )?;
+            }
+        }
+        Ok(file)
+    }
+
+    /// Write a record as a single row or line.
+    fn write(&mut self, record: &ExportRecord) -> Result<(), ExportError> {
+        match self.format {
+            ExportFormat::Csv => {
+                if !self.wrote_header {
+                    let header: Vec<_> = record.iter().map(|(key, _)| *key).collect();
+                    writeln!(self.writer, "{}", header.join(","))?;
+                    self.wrote_header = true;
+                }
+                let row: Vec<_> = record.iter().map(|(_, value)| csv_value(value)).collect();
+                writeln!(self.writer, "{}", row.join(","))?;
+            }
+            ExportFormat::JsonLines => writeln!(self.writer, "{}", json_object(record))?,
+        }
+        Ok(())
+    }
+}
+
+/// Format a value as a CSV field, quoting it if needed.
+fn csv_value(value: &ExportValue) -> String {
+    match value {
+        ExportValue::Null => String::new(),
+        ExportValue::Bool(value) => value.to_string(),
+        ExportValue::Int(value) => value.to_string(),
+        ExportValue::Float(value) => value.to_string(),
+        ExportValue::Str(value) if value.contains([',', '"', '\n', '\r', ' ']) => {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        }
+        ExportValue::Str(value) => value.clone(),
+    }
+}
+
+/// Format a record as a JSON object.
+fn json_object(record: &ExportRecord) -> String {
+    let mut out = String::from("{");
+    for (i, (key, value)) in record.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{}:", json_string(key));
+        match value {
+            ExportValue::Bool(value) => out.push_str(&value.to_string()),
+            ExportValue::Int(value) => out.push_str(&value.to_string()),
+            // JSON has no infinities or NaN
+            ExportValue::Float(value) if value.is_finite() => out.push_str(&value.to_string()),
+            ExportValue::Null | ExportValue::Float(_) => out.push_str("null"),
+            ExportValue::Str(value) => out.push_str(&json_string(value)),
+        }
+    }
+    out.push('}');
+    out
+}
+
+/// Format a string as a quoted and escaped JSON string.
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Resource containing the files statistics are being exported to.
+#[derive(Resource)]
+pub struct Exporter {
+    /// The directory the files are in.
+    pub run_dir: PathBuf,
+    /// The number of neural frames between each exported sample.
+    interval: u32,
+    /// The population statistics, one record per sample.
+    population: ExportFile,
+    /// The world summaries, one record per sample.
+    world: ExportFile,
+    /// The Smittys, one record per founding, birth and death.
+    smittys: ExportFile,
+}
+
+impl Exporter {
+    /// Create a new run directory within the configured export directory and
+    /// open the export files inside it.
+    pub fn create(config: &SimConfig, export_dir: &Path) -> Result<Self, ExportError> {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let run_dir = export_dir.join(format!("run-{}-seed-{}", started, config.seed));
+        fs::create_dir_all(&run_dir)?;
+
+        let provenance = provenance_record(config);
+        let format = config.export_format;
+        Ok(Self {
+            interval: config.export_interval.max(1),
+            population: ExportFile::create(&run_dir, "population", format, &provenance)?,
+            world: ExportFile::create(&run_dir, "world", format, &provenance)?,
+            smittys: ExportFile::create(&run_dir, "smittys", format, &provenance)?,
+            run_dir,
+        })
+    }
+
+    /// Write everything buffered so far out to the files.
+    fn flush(&mut self) -> Result<(), ExportError> {
+        self.population.writer.flush()?;
+        self.world.writer.flush()?;
+        self.smittys.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Build the record describing how the run was started.
+fn provenance_record(config: &SimConfig) -> ExportRecord {
+    vec![
+        ("program", env!("CARGO_PKG_NAME").into()),
+        ("version", env!("CARGO_PKG_VERSION").into()),
+        ("seed", config.seed.into()),
+        (
+            "map",
+            config
+                .map
+                .as_ref()
+                .map(|map| map.display().to_string())
+                .as_deref()
+                .into(),
+        ),
+        ("headless", config.headless.into()),
+        ("frames", config.frames.into()),
+        ("export_format", config.export_format.extension().into()),
+        ("export_interval", config.export_interval.into()),
+        (
+            "metabolic_exponent",
+            config.allometry.metabolic_exponent.into(),
+        ),
+        ("speed_exponent", config.allometry.speed_exponent.into()),
+        (
+            "strength_exponent",
+            config.allometry.strength_exponent.into(),
+        ),
+        ("season_period", config.season_period.into()),
+        ("flood_range", config.flood_range.into()),
+    ]
+}
+
+/// Plugin that exports statistics when an export directory is configured.
+pub struct ExportPlugin;
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // Open the files before anything happens
+            .add_startup_system(init_exporter_system)
+            // Record births and deaths before the dead are despawned
+            .add_simulation_system(
+                FrameUpdateStage::Cleanup,
+                export_smittys_system.run_in_state(SimulationState::Run),
+            )
+            // Export samples once they've been recorded
+            .add_simulation_system(
+                FrameUpdateStage::UpdateIndex,
+                export_samples_system
+                    .run_in_state(SimulationState::Run)
+                    .run_if(is_neural_update_frame_system)
+                    .after(record_stats_system),
+            );
+    }
+}
+
+/// System to open the export files, if exporting is enabled.
+fn init_exporter_system(mut commands: Commands, config: Res<SimConfig>) {
+    let export_dir = match &config.export_dir {
+        Some(export_dir) => export_dir,
+        None => return,
+    };
+
+    match Exporter::create(&config, export_dir) {
+        Ok(exporter) => {
+            info!("exporting statistics to {}", exporter.run_dir.display());
+            commands.insert_resource(exporter);
+        }
+        Err(err) => error!("not exporting statistics: {}", err),
+    }
+}
+
+/// Stop exporting if writing failed, rather than failing every frame.
+fn handle_export_result(commands: &mut Commands, result: Result<(), ExportError>) {
+    if let Err(err) = result {
+        error!("stopped exporting statistics: {}", err);
+        commands.remove_resource::<Exporter>();
+    }
+}
+
+/// System to export a record for each Smitty founded, born or killed this
+/// frame.
+fn export_smittys_system(
+    mut commands: Commands,
+    exporter: Option<ResMut<Exporter>>,
+    sim_time: Res<SimTime>,
+    mut deaths: EventReader<SmittyDeathEvent>,
+    born: Query<Entity, Added<SimEntityEnergy>>,
+    smittys: Query<(
+        &SimEntityPosRot,
+        &SimEntityEnergy,
+        &SimEntityTraits,
+        &SimEntitySpecies,
+        &SimEntityLineage,
+        &SimEntityAge,
+    )>,
+) {
+    let mut exporter = match exporter {
+        Some(exporter) => exporter,
+        None => return,
+    };
+
+    // An entity could die more than once in a frame
+    let mut dead = HashSet::new();
+    let events = born.iter().map(|entity| (entity, None)).chain(
+        deaths
+            .iter()
+            .filter(|death| dead.insert(death.entity))
+            .map(|death| (death.entity, Some(death.cause))),
+    );
+
+    let mut result = Ok(());
+    for (entity, cause) in events {
+        let (pos, energy, traits, species, lineage, age) = match smittys.get(entity) {
+            Ok(smitty) => smitty,
+            Err(_) => continue,
+        };
+        let (cause_name, killer) = match cause {
+            None => (None, None),
+            Some(DeathCause::WorldEdge) => (Some("world_edge"), None),
+            Some(DeathCause::Starvation) => (Some("starvation"), None),
+            Some(DeathCause::OldAge) => (Some("old_age"), None),
+            Some(DeathCause::Killed { by }) => (Some("killed"), Some(by)),
+        };
+        // Founders are placed rather than born, so they aren't counted among
+        // the births in the statistics either
+        let event = match cause {
+            None if lineage.parent.is_none() => "founding",
+            None => "birth",
+            Some(_) => "death",
+        };
+        let record = vec![
+            ("event", event.into()),
+            ("neural_frame", sim_time.neural_frame.into()),
+            ("world_frame", sim_time.world_frame.into()),
+            ("entity", entity.into()),
+            ("species", species.0.into()),
+            ("parent", lineage.parent.into()),
+            ("generation", lineage.generation.into()),
+            ("x", pos.0.x.into()),
+            ("y", pos.0.y.into()),
+            ("energy", energy.0.into()),
+            ("age", age.seconds().into()),
+            ("cause", cause_name.into()),
+            ("killed_by", killer.into()),
+            ("max_move_speed", traits.max_move_speed.into()),
+            ("max_rot_speed", traits.max_rot_speed.into()),
+            ("swim_ability", traits.swim_ability.into()),
+            ("size", traits.size.into()),
+            ("density", traits.density.into()),
+            ("mass", traits.mass().into()),
+            ("hue", traits.hue.into()),
+            ("armour", traits.armour.into()),
+            ("diet", traits.diet.into()),
+            ("vision_rays", (traits.vision_ray_count() as u32).into()),
+            ("vision_range", traits.vision_range.into()),
+            ("vision_fov", traits.vision_fov.into()),
+            ("lifespan", traits.lifespan.into()),
+        ];
+        result = exporter.smittys.write(&record);
+        if result.is_err() {
+            break;
+        }
+    }
+    handle_export_result(&mut commands, result);
+}
+
+/// System to export the latest statistics sample and a summary of the world,
+/// every `export_interval` neural frames.
+fn export_samples_system(
+    mut commands: Commands,
+    exporter: Option<ResMut<Exporter>>,
+    sim_time: Res<SimTime>,
+    stats: Res<SimStats>,
+    simworld: Res<SimWorld>,
+    season: Res<Season>,
+) {
+    let mut exporter = match exporter {
+        Some(exporter) => exporter,
+        None => return,
+    };
+    if sim_time.neural_frame % exporter.interval != 0 {
+        return;
+    }
+
+    let mut result = Ok(());
+    if let Some(sample) = stats.latest() {
+        let traits = &sample.mean_traits;
+        let record = vec![
+            ("neural_frame", sample.neural_frame.into()),
+            ("world_frame", sample.world_frame.into()),
+            ("population", sample.population.into()),
+            ("births", sample.births.into()),
+            ("deaths", sample.deaths.into()),
+            ("species", sample.species.into()),
+            ("energy_mean", sample.energy_mean.into()),
+            ("energy_min", sample.energy_min.into()),
+            ("energy_max", sample.energy_max.into()),
+            ("total_food", sample.total_food.into()),
+            ("mean_max_move_speed", traits.max_move_speed.into()),
+            ("mean_max_rot_speed", traits.max_rot_speed.into()),
+            ("mean_swim_ability", traits.swim_ability.into()),
+            ("mean_size", traits.size.into()),
+            ("mean_density", traits.density.into()),
+            ("mean_armour", traits.armour.into()),
+            ("mean_diet", traits.diet.into()),
+            ("mean_vision_rays", traits.vision_rays.into()),
+            ("mean_vision_range", traits.vision_range.into()),
+            ("mean_vision_fov", traits.vision_fov.into()),
+            ("mean_lifespan", traits.lifespan.into()),
+            ("signal_mean", sample.signal_mean.into()),
+            ("signallers", sample.signallers.into()),
+            (
+                "signal_kinship_correlation",
+                sample.signal_kinship_correlation.into(),
+            ),
+        ];
+        result = exporter.population.write(&record);
+    }
+
+    if result.is_ok() {
+        let count = |tile_type| {
+            simworld
+                .tiles()
+                .filter(|tile| tile.tile_type == tile_type)
+                .count() as u32
+        };
+        let record = vec![
+            ("neural_frame", sim_time.neural_frame.into()),
+            ("world_frame", sim_time.world_frame.into()),
+            (
+                "boundary",
+                format!("{:?}", simworld.boundary()).as_str().into(),
+            ),
+            (
+                "total_food",
+                simworld.tiles().map(|tile| tile.food).sum::<f32>().into(),
+            ),
+            (
+                "total_max_food",
+                simworld
+                    .tiles()
+                    .map(|tile| tile.max_food)
+                    .sum::<f32>()
+                    .into(),
+            ),
+            (
+                "total_meat",
+                simworld.tiles().map(|tile| tile.meat).sum::<f32>().into(),
+            ),
+            ("land_tiles", count(SimTileType::Land).into()),
+            ("water_tiles", count(SimTileType::Water).into()),
+            ("rock_tiles", count(SimTileType::Rock).into()),
+            ("season", season.name().into()),
+            ("season_phase", season.phase().into()),
+            ("temperature", season.temperature().into()),
+            ("water_level", season.water_level().into()),
+        ];
+        result = exporter.world.write(&record);
+    }
+
+    // Keep the files readable while the simulation is still running
+    let result = result.and_then(|_| exporter.flush());
+    handle_export_result(&mut commands, result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csv_str(value: &str) -> String {
+        csv_value(&value.into())
+    }
+
+    #[test]
+    fn csv_plain_values() {
+        assert_eq!(csv_str("Torus"), "Torus");
+        assert_eq!(csv_value(&ExportValue::Null), "");
+        assert_eq!(csv_value(&3u32.into()), "3");
+        assert_eq!(csv_value(&true.into()), "true");
+    }
+
+    #[test]
+    fn csv_quotes_commas_and_newlines() {
+        assert_eq!(csv_str("a,b"), "\"a,b\"");
+        assert_eq!(csv_str("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_str("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_str("two\r\nlines"), "\"two\r\nlines\"");
+        assert_eq!(csv_str("my map.png"), "\"my map.png\"");
+    }
+
+    #[test]
+    fn json_string_escapes() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(json_string("back\\slash"), "\"back\\\\slash\"");
+        assert_eq!(json_string("two\nlines"), "\"two\\nlines\"");
+        assert_eq!(json_string("a,b"), "\"a,b\"");
+    }
+
+    #[test]
+    fn json_string_escapes_control_characters() {
+        assert_eq!(json_string("tab\there"), "\"tab\\u0009here\"");
+        assert_eq!(json_string("\r\u{1}\u{7f}"), "\"\\u000d\\u0001\\u007f\"");
+    }
+
+    #[test]
+    fn json_object_values() {
+        let record: ExportRecord = vec![
+            ("null", ExportValue::Null),
+            ("bool", false.into()),
+            ("int", 7u32.into()),
+            ("float", 0.5f32.into()),
+            ("nan", f32::NAN.into()),
+            ("str", "a\"b".into()),
+        ];
+        assert_eq!(
+            json_object(&record),
+            "{\"null\":null,\"bool\":false,\"int\":7,\"float\":0.5,\"nan\":null,\"str\":\"a\\\"b\"}"
+        );
+    }
+}
@@ -1,11 +1,23 @@
 use crate::{
-    ecs::{SimTime, SimulationMode, SimulationState},
-    simworld::{SimTile, SimWorld, WORLD_SIZE},
+    camera::{CameraControls, FitCameraEvent, MainCameraMarker},
+    ecs::{
+        SimEntityAge, SimEntityBrain, SimEntityBrainInputs, SimEntityBrainOutputs, SimEntityEnergy,
+        SimEntityLineage, SimEntityPosRot, SimEntityTraits, SimSpeed, SimTime, SimulationMode,
+        SimulationState, SmittyAction, SmittyActions, NETWORK_UPDATE_PERIOD, SIM_MAX_SPEED,
+        SIM_MIN_SPEED, SMITTY_SCALE,
+    },
+    editor::{EditorTool, LoadMapEvent, SaveMapEvent, TileEditor},
+    overlay::MapOverlay,
+    scent::ScentChannel,
+    season::Season,
+    simworld::{SimTile, SimTileType, SimWorld, WorldBoundary, MAX_FOOD},
+    spatial::SpatialIndex,
+    stats::{SimStats, StatsSample},
 };
 use bevy::{math::Vec3Swizzles, prelude::*};
 use bevy_egui::{
     egui,
-    egui::{FontId, RichText, Widget},
+    egui::{plot, FontId, RichText, Widget},
     EguiContext, EguiPlugin,
 };
 use iyes_loopless::state::{CurrentState, NextState};
@@ -13,6 +25,19 @@ use num_format::{Locale, ToFormattedString};
 
 const NUM_LOCAL: Locale = Locale::en;
 
+/// The smallest size of the neural network diagram in the brain window, which
+/// grows taller to fit its largest layer.
+const BRAIN_DIAGRAM_SIZE: egui::Vec2 = egui::vec2(320.0, 220.0);
+/// The radius of each node in the neural network diagram.
+const BRAIN_NODE_RADIUS: f32 = 7.0;
+/// The distance between the centres of neighbouring nodes in a layer of the
+/// neural network diagram, which leaves a gap between them.
+const BRAIN_NODE_SPACING: f32 = 2.5 * BRAIN_NODE_RADIUS;
+/// The tallest the brain window gets before its diagram has to be scrolled.
+const BRAIN_WINDOW_MAX_HEIGHT: f32 = 480.0;
+/// The height of the plot in the statistics window.
+const STATS_PLOT_HEIGHT: f32 = 200.0;
+
 /// Plugin to organize UI systems for the simulator.
 pub struct EvoSimGuiPlugin;
 
@@ -22,12 +47,99 @@ impl Plugin for EvoSimGuiPlugin {
             // Add the resource to keep track of the currently selected smitty
             .init_resource::<SelectedSmitty>()
             .init_resource::<CursorState>()
+            .init_resource::<SimControls>()
             // Add the EGui plugin until Bevy's UI handler is better :/
             .add_plugin(EguiPlugin)
             // Add cursor update system
             .add_system_to_stage(CoreStage::First, update_cursor_pos)
+            // Add Smitty selection
+            .add_startup_system(init_selection_ring_system)
+            .add_system(select_smitty_system)
+            .add_system(update_selection_ring_system.after(select_smitty_system))
             // Add the inspector window for Smitty
-            .add_system(smitty_inspector_egui_system);
+            .add_system(smitty_inspector_egui_system.after(select_smitty_system))
+            // Add the statistics charts
+            .add_system(stats_egui_system)
+            // Add the overlay selection and legend
+            .add_system(overlay_egui_system)
+            // Add the tile editor's tools
+            .add_system(editor_egui_system);
+    }
+}
+
+/// Resource to keep track of the values entered into the simulation controls.
+#[derive(Resource)]
+pub struct SimControls {
+    /// The number of frames to run when the "Run Frames" button is clicked.
+    pub run_frames: u32,
+    /// The chart shown in the statistics window.
+    pub stats_chart: StatsChart,
+}
+
+impl Default for SimControls {
+    fn default() -> Self {
+        Self {
+            run_frames: NETWORK_UPDATE_PERIOD * 10,
+            stats_chart: StatsChart::Population,
+        }
+    }
+}
+
+/// The charts that can be shown in the statistics window.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StatsChart {
+    /// Population size, births, deaths and species.
+    Population,
+    /// Mean, minimum and maximum energy.
+    Energy,
+    /// Total food in the world.
+    Food,
+    /// The mean of each trait.
+    Traits,
+    /// How much Smittys signal, and whether they signal more around kin.
+    Signalling,
+}
+
+impl StatsChart {
+    /// Get the name and value of each series plotted on this chart.
+    #[allow(clippy::type_complexity)]
+    fn series(&self) -> &'static [(&'static str, fn(&StatsSample) -> f32)] {
+        match self {
+            Self::Population => &[
+                ("Population", |s| s.population as f32),
+                ("Births", |s| s.births as f32),
+                ("Deaths", |s| s.deaths as f32),
+                ("Species", |s| s.species as f32),
+            ],
+            Self::Energy => &[
+                ("Mean", |s| s.energy_mean),
+                ("Min", |s| s.energy_min),
+                ("Max", |s| s.energy_max),
+            ],
+            Self::Food => &[("Total food", |s| s.total_food)],
+            Self::Traits => &[
+                ("Move speed", |s| s.mean_traits.max_move_speed),
+                ("Rotation speed", |s| s.mean_traits.max_rot_speed),
+                ("Swim ability", |s| s.mean_traits.swim_ability),
+                ("Size", |s| s.mean_traits.size),
+                ("Density", |s| s.mean_traits.density),
+                ("Armour", |s| s.mean_traits.armour),
+                ("Diet", |s| s.mean_traits.diet),
+                ("Vision rays", |s| s.mean_traits.vision_rays),
+                ("Vision range", |s| s.mean_traits.vision_range),
+                ("Field of view", |s| s.mean_traits.vision_fov),
+                ("Lifespan", |s| s.mean_traits.lifespan),
+            ],
+            Self::Signalling => &[
+                ("Mean signal", |s| s.signal_mean),
+                ("Signalling", |s| {
+                    s.signallers as f32 / s.population.max(1) as f32
+                }),
+                ("Signal/kinship correlation", |s| {
+                    s.signal_kinship_correlation
+                }),
+            ],
+        }
     }
 }
 
@@ -43,46 +155,139 @@ pub struct CursorState {
 #[derive(Default, Resource)]
 pub struct SelectedSmitty(pub Option<Entity>);
 
-pub struct SmittyRaycastSet;
+/// Marker for the ring drawn around the selected Smitty.
+#[derive(Component)]
+pub struct SelectionRingMarker;
 
 /// System to update the raycast sender stuff and things and stuff im high idk and idc.
 fn update_cursor_pos(
-    mut cursor: EventReader<CursorMoved>,
+    windows: Res<Windows>,
+    simworld: Res<SimWorld>,
     mut cursor_state: ResMut<CursorState>,
-    source_query: Query<(&Camera, &GlobalTransform)>,
+    source_query: Query<(&Camera, &GlobalTransform), With<MainCameraMarker>>,
 ) {
-    // Grab the most recent cursor event if it exists:
-    let cursor_position = match cursor.iter().last() {
-        Some(cursor_moved) => cursor_moved.position,
+    // Check every frame, since the camera can move without the cursor moving
+    let cursor_position = match windows
+        .get_primary()
+        .and_then(|window| window.cursor_position())
+    {
+        Some(cursor_position) => cursor_position,
+        None => return,
+    };
+    // Pull the world camera
+    let (camera, cam_transform) = match source_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+
+    // The camera's projection can't be inverted while the window has no size,
+    // such as when it's minimized
+    let wp = match camera.viewport_to_world(cam_transform, cursor_position) {
+        Some(ray) => ray.origin.xy(),
         None => return,
     };
-    // Pull the first (and should be ONLY `RaycastSource` in the scene
-    let (camera, cam_transform) = source_query.iter().next().unwrap();
 
     // Update the cursor state
     cursor_state.screen_pos = cursor_position;
-    let wp = camera
-        .viewport_to_world(cam_transform, cursor_position)
-        .unwrap()
-        .origin
-        .xy();
     cursor_state.world_pos = wp;
-    cursor_state.tile_pos =
-        if wp.x >= 0.0 && wp.y >= 0.0 && wp.x < WORLD_SIZE.0 as f32 && wp.y < WORLD_SIZE.0 as f32 {
-            let f = wp.floor().as_uvec2();
-            Some((f.x as usize, f.y as usize))
-        } else {
-            None
-        };
+    cursor_state.tile_pos = simworld.tile_pos_at(wp);
+}
+
+/// System to spawn the (initially hidden) ring around the selected Smitty.
+fn init_selection_ring_system(mut commands: Commands, assets: Res<AssetServer>) {
+    commands.spawn((
+        SpriteBundle {
+            texture: assets.load("ring.png"),
+            sprite: Sprite {
+                color: Color::YELLOW,
+                custom_size: Some(Vec2::splat(1.0)),
+                ..default()
+            },
+            visibility: Visibility::INVISIBLE,
+            ..default()
+        },
+        SelectionRingMarker,
+    ));
+}
+
+/// System to select the Smitty under the cursor when the world is clicked, or
+/// deselect when clicking empty space.
+fn select_smitty_system(
+    mouse: Res<Input<MouseButton>>,
+    cursor_state: Res<CursorState>,
+    simworld: Res<SimWorld>,
+    index: Res<SpatialIndex>,
+    traits: Query<&SimEntityTraits>,
+    mut selected_smitty: ResMut<SelectedSmitty>,
+    editor: Res<TileEditor>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    // Ignore clicks meant for the UI or the editor
+    if !mouse.just_pressed(MouseButton::Left)
+        || editor.enabled
+        || egui_context.ctx_mut().is_pointer_over_area()
+    {
+        return;
+    }
+
+    // Pick the nearest Smitty with its sprite under the cursor
+    selected_smitty.0 = index
+        .nearest(&simworld, cursor_state.world_pos, 4)
+        .into_iter()
+        .find(|hit| {
+            traits
+                .get(hit.entity)
+                .map_or(false, |traits| hit.distance <= traits.radius())
+        })
+        .map(|hit| hit.entity);
+    info!("selected Smitty: {:?}", selected_smitty.0);
+}
+
+/// System to keep the selection ring around the selected Smitty, hiding it
+/// when nothing is selected.
+fn update_selection_ring_system(
+    selected_smitty: Res<SelectedSmitty>,
+    smittys: Query<(&SimEntityPosRot, &SimEntityTraits)>,
+    mut ring: Query<(&mut Transform, &mut Visibility), With<SelectionRingMarker>>,
+) {
+    let (mut ring_transform, mut ring_visibility) = ring.single_mut();
+    match selected_smitty
+        .0
+        .and_then(|selected| smittys.get(selected).ok())
+    {
+        Some((pos, traits)) => {
+            ring_transform.translation = pos.0.extend(2.0);
+            ring_transform.scale = Vec3::splat(1.5 * SMITTY_SCALE * traits.size);
+            ring_visibility.is_visible = true;
+        }
+        None => ring_visibility.is_visible = false,
+    }
 }
 
 /// System to update the inspector window for selected smittys (if one is selected).
+#[allow(clippy::too_many_arguments)]
 fn smitty_inspector_egui_system(
-    selected_smitty: Res<SelectedSmitty>,
+    mut selected_smitty: ResMut<SelectedSmitty>,
+    mut sim_controls: ResMut<SimControls>,
+    mut sim_speed: ResMut<SimSpeed>,
+    mut camera_controls: ResMut<CameraControls>,
+    mut fit_camera: EventWriter<FitCameraEvent>,
     cursor_state: Res<CursorState>,
     sim_time: Res<SimTime>,
-    sim_world: Res<SimWorld>,
+    season: Res<Season>,
+    mut sim_world: ResMut<SimWorld>,
     sim_state: Res<CurrentState<SimulationState>>,
+    smittys: Query<(
+        &SimEntityPosRot,
+        &SimEntityTraits,
+        &SimEntityEnergy,
+        &SimEntityBrainInputs,
+        &SimEntityBrainOutputs,
+        &SmittyActions,
+        &SimEntityLineage,
+        &SimEntityAge,
+    )>,
+    brains: Query<&SimEntityBrain>,
     mut egui_context: ResMut<EguiContext>,
     mut commands: Commands,
 ) {
@@ -107,6 +312,60 @@ fn smitty_inspector_egui_system(
             ));
             //ui.separator();
 
+            // Season
+            ui.label(format!(
+                "Season: {} ({:.0}% through the year)",
+                season.name(),
+                season.phase() * 100.0
+            ));
+            ui.label(format!("Temperature: {:+.2}", season.temperature()));
+            ui.label(format!("Water level: {:+} tiles", season.water_level()));
+
+            // Simulation speed
+            ui.horizontal(|ui| {
+                let mut max = sim_speed.multiplier.is_none();
+                let mut multiplier = sim_speed.multiplier.unwrap_or(SIM_MAX_SPEED);
+                ui.add_enabled(
+                    !max,
+                    egui::Slider::new(&mut multiplier, SIM_MIN_SPEED..=SIM_MAX_SPEED)
+                        .logarithmic(true)
+                        .text("Speed")
+                        .suffix("×"),
+                );
+                ui.checkbox(&mut max, "Max");
+
+                let new_multiplier = if max { None } else { Some(multiplier) };
+                if new_multiplier != sim_speed.multiplier {
+                    sim_speed.multiplier = new_multiplier;
+                }
+            });
+            ui.label(format!(
+                "Ticks per second: {}",
+                (sim_speed.ticks_per_second().round() as u32).to_formatted_string(&NUM_LOCAL)
+            ));
+
+            // Camera controls
+            ui.horizontal(|ui| {
+                if ui.button("Fit World").clicked() {
+                    fit_camera.send(FitCameraEvent);
+                }
+                ui.checkbox(&mut camera_controls.follow, "Follow selected");
+            });
+
+            // World boundary selection
+            let mut boundary = sim_world.boundary();
+            egui::ComboBox::from_label("World edges")
+                .selected_text(format!("{:?}", boundary))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut boundary, WorldBoundary::Torus, "Torus");
+                    ui.selectable_value(&mut boundary, WorldBoundary::Walled, "Walled");
+                    ui.selectable_value(&mut boundary, WorldBoundary::Deadly, "Deadly");
+                });
+            if boundary != sim_world.boundary() {
+                info!("changing world boundary to {:?}", boundary);
+                sim_world.set_boundary(boundary);
+            }
+
             // Add buttons
             ui.horizontal(|ui| {
                 // Start button
@@ -116,6 +375,7 @@ fn smitty_inspector_egui_system(
                 {
                     info!("resuming simulation");
 
+                    commands.insert_resource(NextState(SimulationMode::Auto));
                     commands.insert_resource(NextState(SimulationState::Run));
                 }
 
@@ -137,20 +397,39 @@ fn smitty_inspector_egui_system(
                     info!("stepping simulation by one frame");
 
                     commands.insert_resource(NextState(SimulationMode::Single));
+                    commands.insert_resource(NextState(SimulationState::Run));
                 }
 
                 // Step neural frame button
-                /* if ui
+                if ui
                     .add_enabled(
                         s == SimulationState::Stop,
                         egui::Button::new("Step Neural Frame"),
                     )
                     .clicked()
                 {
-                    info!("stepping simulation by one frame");
+                    info!("stepping simulation to the next neural frame");
 
                     commands.insert_resource(NextState(SimulationMode::Brain));
-                } */
+                    commands.insert_resource(NextState(SimulationState::Run));
+                }
+            });
+
+            // Run a number of frames
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::DragValue::new(&mut sim_controls.run_frames).clamp_range(1..=u32::MAX),
+                );
+                if ui
+                    .add_enabled(s == SimulationState::Stop, egui::Button::new("Run Frames"))
+                    .clicked()
+                {
+                    info!("running simulation for {} frames", sim_controls.run_frames);
+
+                    let until = sim_time.world_frame.saturating_add(sim_controls.run_frames);
+                    commands.insert_resource(NextState(SimulationMode::UntilFrame(until)));
+                    commands.insert_resource(NextState(SimulationState::Run));
+                }
             });
         });
 
@@ -176,13 +455,141 @@ fn smitty_inspector_egui_system(
         .resizable(false)
         .show(egui_context.ctx_mut(), |ui| {
             // Check if a smitty is selected
-            if let Some(selected) = selected_smitty.0 {
-                ui.label(format!("Entity: {:?}", selected));
-            } else {
-                ui.label("No entity selected");
-            }
+            let selected = match selected_smitty.0 {
+                Some(selected) => selected,
+                None => {
+                    ui.label("No entity selected");
+                    return;
+                }
+            };
+            let (pos, traits, energy, inputs, outputs, actions, lineage, age) =
+                match smittys.get(selected) {
+                    Ok(smitty) => smitty,
+                    Err(_) => {
+                        // It died since being selected
+                        selected_smitty.0 = None;
+                        ui.label("No entity selected");
+                        return;
+                    }
+                };
+
+            ui.label(format!("Entity: {:?}", selected));
+            ui.label(format!("Position: ({:.2}, {:.2})", pos.0.x, pos.0.y));
+            ui.label(format!("Heading: {:.1}°", pos.1.to_degrees()));
+            ui.label(format!("Energy: {:.4}", energy.0));
+            ui.label(format!(
+                "Age: {:.1} s ({:.0}% of lifespan{})",
+                age.seconds(),
+                age.life_fraction(traits) * 100.0,
+                if age.is_mature(traits) {
+                    ""
+                } else {
+                    ", immature"
+                }
+            ));
+            ui.label(format!("Generation: {}", lineage.generation));
+
+            ui.collapsing("Traits", |ui| {
+                ui.label(format!("Max move speed: {:.2} m/s", traits.max_move_speed));
+                ui.label(format!(
+                    "Max rotation speed: {:.2} rad/s",
+                    traits.max_rot_speed
+                ));
+                ui.label(format!("Swim ability: {:.2}", traits.swim_ability));
+                ui.label(format!("Size: {:.2}", traits.size));
+                ui.label(format!("Density: {:.2}", traits.density));
+                ui.label(format!("Mass: {:.2}", traits.mass()));
+                ui.horizontal(|ui| {
+                    ui.label(format!("Hue: {:.2}", traits.hue));
+                    swatch(ui, traits.color());
+                });
+                ui.label(format!("Armour: {:.2}", traits.armour));
+                ui.label(format!("Diet: {:.2}", traits.diet));
+                ui.label(format!("Vision rays: {}", traits.vision_ray_count()));
+                ui.label(format!("Vision range: {:.2} m", traits.vision_range));
+                ui.label(format!(
+                    "Field of view: {:.0}°",
+                    traits.vision_fov.to_degrees()
+                ));
+                ui.label(format!("Lifespan: {:.0} s", traits.lifespan));
+            });
+            ui.collapsing("Brain inputs", |ui| {
+                ui.label(format!("Energy: {:.4}", inputs.energy));
+                ui.label(format!("Age: {:.4}", inputs.age));
+                ui.label(format!("Temperature: {:.4}", inputs.temperature));
+                ui.label(format!("Season: {:.4}", inputs.season));
+                ui.label(format!("Tile food: {:.4}", inputs.tile_food));
+                ui.label(format!("Touching: {:.4}", inputs.touching));
+                ui.label(format!(
+                    "Nearest proximity: {:.4}",
+                    inputs.nearest_proximity
+                ));
+                ui.label(format!("Nearest bearing: {:.4}", inputs.nearest_bearing));
+                for (i, ray) in inputs
+                    .vision
+                    .iter()
+                    .take(traits.vision_ray_count())
+                    .enumerate()
+                {
+                    ui.label(format!(
+                        "Ray {}: proximity {:.2}, water {}, food {:.2}, Smitty {}, kinship {:.2}",
+                        i + 1,
+                        ray.proximity,
+                        ray.water,
+                        ray.food,
+                        ray.smitty,
+                        ray.kinship
+                    ));
+                }
+                ui.label(format!(
+                    "Signal heard: strength {:.2}, tone {:.2}, bearing {:.2}",
+                    inputs.signal.strength, inputs.signal.tone, inputs.signal.bearing
+                ));
+                for (channel, sense) in ScentChannel::ALL.iter().zip(&inputs.scent) {
+                    ui.label(format!(
+                        "{}: level {:.2}, forward {:.2}, left {:.2}",
+                        channel.name(),
+                        sense.level,
+                        sense.forward,
+                        sense.left
+                    ));
+                }
+            });
+            ui.collapsing("Brain outputs", |ui| {
+                for action in SmittyAction::ALL {
+                    ui.label(format!("{}: {:.4}", action.name(), outputs.get(action)));
+                }
+            });
+            ui.collapsing("Actions", |ui| {
+                ui.label(format!("Move: {:.2}", actions.move_amt));
+                ui.label(format!("Turn: {:.2}", actions.turn_amt));
+                ui.label(format!("Eat: {}", actions.eat));
+                ui.label(format!("Reproduce: {}", actions.reproduce));
+                ui.label(format!("Attack: {}", actions.attack));
+                ui.label(format!(
+                    "Signal: {:.2} (tone {:.2})",
+                    actions.signal, actions.signal_tone
+                ));
+                for (channel, amount) in ScentChannel::PHEROMONES.iter().zip(actions.pheromones) {
+                    ui.label(format!("{}: {:.2}", channel.name(), amount));
+                }
+            });
         });
 
+    // The brain window for the selected Smitty
+    if let Some(brain) = selected_smitty
+        .0
+        .and_then(|selected| brains.get(selected).ok())
+    {
+        egui::Window::new("Smitty Brain")
+            .resizable(false)
+            .show(egui_context.ctx_mut(), |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(BRAIN_WINDOW_MAX_HEIGHT)
+                    .show(ui, |ui| draw_brain(ui, brain));
+            });
+    }
+
     // The tile inspector window
     egui::Window::new("Inspect Tile")
         .resizable(false)
@@ -192,8 +599,261 @@ fn smitty_inspector_egui_system(
                 ui.label(format!("Type: {:?}", tile.tile_type));
                 ui.label(format!("Current food: {:.4}", tile.food));
                 ui.label(format!("Max food: {:.4}", tile.max_food));
+                ui.label(format!("Meat: {:.4}", tile.meat));
             } else {
                 ui.label("No tile under cursor");
             }
         });
 }
+
+/// System to show the recorded statistics as charts over neural frames.
+fn stats_egui_system(
+    stats: Res<SimStats>,
+    mut sim_controls: ResMut<SimControls>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    egui::Window::new("Statistics").show(egui_context.ctx_mut(), |ui| {
+        // Chart selection
+        let chart = &mut sim_controls.stats_chart;
+        egui::ComboBox::from_label("Chart")
+            .selected_text(format!("{:?}", chart))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(chart, StatsChart::Population, "Population");
+                ui.selectable_value(chart, StatsChart::Energy, "Energy");
+                ui.selectable_value(chart, StatsChart::Food, "Food");
+                ui.selectable_value(chart, StatsChart::Traits, "Traits");
+                ui.selectable_value(chart, StatsChart::Signalling, "Signalling");
+            });
+
+        // Latest values
+        if let Some(latest) = stats.latest() {
+            for (name, value) in chart.series() {
+                ui.label(format!("{}: {:.3}", name, value(latest)));
+            }
+        }
+
+        // One line per series, over neural frames
+        plot::Plot::new("stats_plot")
+            .height(STATS_PLOT_HEIGHT)
+            .legend(plot::Legend::default())
+            .show(ui, |plot_ui| {
+                for (name, value) in chart.series() {
+                    let points: plot::PlotPoints = stats
+                        .samples()
+                        .map(|sample| [sample.neural_frame as f64, value(sample) as f64])
+                        .collect();
+                    plot_ui.line(plot::Line::new(points).name(name));
+                }
+            });
+    });
+}
+
+/// System to pick the map overlay and explain its colors.
+fn overlay_egui_system(mut overlay: ResMut<MapOverlay>, mut egui_context: ResMut<EguiContext>) {
+    egui::Window::new("Map Overlay")
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            // Overlay selection
+            let mut selected = *overlay;
+            egui::ComboBox::from_label("Overlay")
+                .selected_text(selected.name())
+                .show_ui(ui, |ui| {
+                    for option in MapOverlay::ALL {
+                        ui.selectable_value(&mut selected, option, option.name());
+                    }
+                });
+            if selected != *overlay {
+                *overlay = selected;
+            }
+
+            // Legend
+            for (color, meaning) in overlay.legend() {
+                ui.horizontal(|ui| {
+                    swatch(ui, color);
+                    ui.label(meaning);
+                });
+            }
+        });
+}
+
+/// System to show the tile editor's tools and map file controls.
+fn editor_egui_system(
+    mut editor: ResMut<TileEditor>,
+    mut save_map: EventWriter<SaveMapEvent>,
+    mut load_map: EventWriter<LoadMapEvent>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    egui::Window::new("Tile Editor")
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.checkbox(&mut editor.enabled, "Edit with the mouse");
+
+            // Tool selection
+            ui.add_enabled_ui(editor.enabled, |ui| {
+                egui::ComboBox::from_label("Tool")
+                    .selected_text(format!("{:?}", editor.tool))
+                    .show_ui(ui, |ui| {
+                        let tool = &mut editor.tool;
+                        ui.selectable_value(tool, EditorTool::PaintType, "Paint type");
+                        ui.selectable_value(tool, EditorTool::SetFood, "Set food");
+                        ui.selectable_value(tool, EditorTool::SetMaxFood, "Set max food");
+                        ui.selectable_value(tool, EditorTool::FloodFill, "Flood fill");
+                        ui.selectable_value(tool, EditorTool::PlaceSmitty, "Place Smitty");
+                        ui.selectable_value(tool, EditorTool::DeleteSmitty, "Delete Smitty");
+                    });
+
+                // Tool settings
+                ui.add(
+                    egui::Slider::new(&mut editor.brush_radius, 0.0..=10.0).text("Brush radius"),
+                );
+                egui::ComboBox::from_label("Tile type")
+                    .selected_text(format!("{:?}", editor.tile_type))
+                    .show_ui(ui, |ui| {
+                        let tile_type = &mut editor.tile_type;
+                        ui.selectable_value(tile_type, SimTileType::Land, "Land");
+                        ui.selectable_value(tile_type, SimTileType::Water, "Water");
+                        ui.selectable_value(tile_type, SimTileType::Rock, "Rock");
+                    });
+                ui.add(egui::Slider::new(&mut editor.food, 0.0..=MAX_FOOD).text("Food"));
+                ui.add(egui::Slider::new(&mut editor.max_food, 0.0..=MAX_FOOD).text("Max food"));
+            });
+            ui.separator();
+
+            // Map files
+            ui.horizontal(|ui| {
+                ui.label("Map file:");
+                ui.text_edit_singleline(&mut editor.map_path);
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Save Map").clicked() {
+                    save_map.send(SaveMapEvent);
+                }
+                if ui.button("Load Map").clicked() {
+                    load_map.send(LoadMapEvent);
+                }
+            });
+            if let Some(status) = &editor.status {
+                ui.label(status);
+            }
+        });
+}
+
+/// Draw a neural network as a node-link diagram, with nodes shaded by their
+/// values from the last run and edges colored green (positive) or red
+/// (negative) and thickened by their weights. Hovering over a node or an edge
+/// shows its exact values.
+fn draw_brain(ui: &mut egui::Ui, brain: &SimEntityBrain) {
+    let layers = brain.network.layers();
+
+    // Get the sizes of each layer (including the inputs), make the diagram
+    // tall enough for the largest to fit without its nodes overlapping, and
+    // get the position of each node
+    let sizes = std::iter::once(brain.network.num_inputs() as usize)
+        .chain(layers.iter().map(|layer| layer.len()))
+        .collect::<Vec<_>>();
+    let largest = sizes.iter().copied().max().unwrap_or(0);
+    let size = egui::vec2(
+        BRAIN_DIAGRAM_SIZE.x,
+        BRAIN_DIAGRAM_SIZE
+            .y
+            .max(largest as f32 * BRAIN_NODE_SPACING + BRAIN_NODE_RADIUS * 4.0),
+    );
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let rect = response.rect.shrink(BRAIN_NODE_RADIUS * 2.0);
+    let node_pos = |layer: usize, node: usize| {
+        let x = rect.left() + rect.width() * layer as f32 / (sizes.len() - 1) as f32;
+        let y = rect.top() + rect.height() * (node as f32 + 0.5) / sizes[layer] as f32;
+        egui::pos2(x, y)
+    };
+    let activation = |layer: usize, node: usize| {
+        brain
+            .activations
+            .get(layer)
+            .and_then(|values| values.get(node))
+            .copied()
+    };
+    let hover = response.hover_pos();
+    let mut hovered = None;
+
+    // Draw the edges first so the nodes are drawn over them
+    for (i, layer) in layers.iter().enumerate() {
+        for (to, node) in layer.iter().enumerate() {
+            // The first weight is the node's bias
+            for (from, &weight) in node.iter().skip(1).enumerate() {
+                let (a, b) = (node_pos(i, from), node_pos(i + 1, to));
+                let color = if weight >= 0.0 {
+                    egui::Color32::from_rgb(60, 200, 80)
+                } else {
+                    egui::Color32::from_rgb(220, 60, 60)
+                };
+                painter.line_segment([a, b], (0.5 + weight.abs() * 2.0, color));
+
+                if let Some(p) = hover {
+                    if dist_to_segment(p, a, b) < 3.0 {
+                        hovered = Some(format!("Weight: {:.4}", weight));
+                    }
+                }
+            }
+        }
+    }
+
+    // Then draw the nodes
+    for (layer, &size) in sizes.iter().enumerate() {
+        for node in 0..size {
+            let pos = node_pos(layer, node);
+            let value = activation(layer, node);
+            let shade = (value.unwrap_or(0.0).clamp(0.0, 1.0) * 255.0) as u8;
+            painter.circle(
+                pos,
+                BRAIN_NODE_RADIUS,
+                egui::Color32::from_gray(shade),
+                (1.0, egui::Color32::LIGHT_GRAY),
+            );
+
+            if hover.map_or(false, |p| p.distance(pos) <= BRAIN_NODE_RADIUS) {
+                let name = if layer == 0 {
+                    SimEntityBrainInputs::name(node)
+                } else if layer == sizes.len() - 1 {
+                    SmittyAction::ALL
+                        .get(node)
+                        .map(|action| action.name().to_owned())
+                } else {
+                    None
+                };
+                let mut text = match name {
+                    Some(name) => format!("{}\n", name),
+                    None => format!("Layer {}, node {}\n", layer, node),
+                };
+                text += &match value {
+                    Some(value) => format!("Value: {:.4}", value),
+                    None => "Value: not run yet".to_owned(),
+                };
+                if layer > 0 {
+                    text += &format!("\nBias: {:.4}", layers[layer - 1][node][0]);
+                }
+                hovered = Some(text);
+            }
+        }
+    }
+
+    if let Some(text) = hovered {
+        response.on_hover_ui_at_pointer(|ui| {
+            ui.label(text);
+        });
+    }
+}
+
+/// Draw a small square of the given color, such as for a legend.
+fn swatch(ui: &mut egui::Ui, color: Color) {
+    let [r, g, b, _] = color.as_rgba_f32();
+    let color = egui::Color32::from_rgb((r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8);
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(14.0, 14.0), egui::Sense::hover());
+    ui.painter().rect_filled(rect, 2.0, color);
+}
+
+/// Get the distance from a point to the closest point on a line segment.
+fn dist_to_segment(p: egui::Pos2, a: egui::Pos2, b: egui::Pos2) -> f32 {
+    let ab = b - a;
+    let t = ((p - a).dot(ab) / ab.length_sq()).clamp(0.0, 1.0);
+    p.distance(a + ab * t)
+}
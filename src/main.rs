@@ -4,94 +4,201 @@
 //! - With love and care, CJ
 
 // my babies
+mod camera;
+mod config;
 mod ecs;
+mod editor;
+mod export;
 mod gui;
+mod map;
 mod net;
+mod overlay;
+mod scent;
+mod season;
+mod signal;
 mod simworld;
+mod spatial;
+mod stats;
+mod vision;
 
 // ~~ Imports ~~ //
-use crate::gui::{EvoSimGuiPlugin, SmittyRaycastSet};
+use crate::{
+    camera::CameraControlPlugin, editor::TileEditorPlugin, gui::EvoSimGuiPlugin,
+    overlay::MapOverlayPlugin,
+};
 use bevy::{
+    app::AppExit,
     log::{Level, LogPlugin},
     prelude::*,
-    render::camera::ScalingMode,
 };
+use config::*;
 use ecs::*;
+use export::ExportPlugin;
+use iyes_loopless::prelude::*;
 use net::*;
+use rand::Rng;
+use scent::ScentPlugin;
+use season::SeasonPlugin;
+use signal::SignalPlugin;
 use simworld::*;
+use spatial::SpatialIndexPlugin;
+use stats::{SimStats, StatsPlugin};
+use std::f32::consts::PI;
+use vision::VisionPlugin;
 
 /// Start le simulation
 fn main() {
-    App::new()
-        // Background color & antialiasing (can use FXAA with bevy 0.9)
-        .insert_resource(ClearColor(Color::BLACK))
-        .insert_resource(Msaa { samples: 4 })
-        // Plugins
-        .add_plugins(
-            DefaultPlugins
-                // Enable debug logging but disable for "loud" crates
-                .set(LogPlugin {
-                    level: Level::DEBUG,
-                    filter: "wgpu=warn,bevy_ecs=info,naga=info".to_owned(),
-                })
-                // Window configuration
-                .set(WindowPlugin {
-                    window: WindowDescriptor {
-                        title: format!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
-                        resizable: true,
+    // Read the config from the command line
+    let config = match SimConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    // Enable debug logging but disable for "loud" crates
+    let log_plugin = LogPlugin {
+        level: Level::DEBUG,
+        filter: "wgpu=warn,bevy_ecs=info,naga=info".to_owned(),
+    };
+
+    let mut app = App::new();
+    if config.headless {
+        // Just enough to run the simulation as fast as possible
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(log_plugin)
+            // The Smittys still have transforms, even with nothing to draw
+            .add_plugin(TransformPlugin)
+            // Start running as fast as possible right away and quit once
+            // stopped
+            .insert_resource(SimSpeed::max())
+            .insert_resource(NextState(SimulationState::Run))
+            .add_system(exit_when_stopped_system);
+    } else {
+        app
+            // Background color & antialiasing (can use FXAA with bevy 0.9)
+            .insert_resource(ClearColor(Color::BLACK))
+            .insert_resource(Msaa { samples: 4 })
+            // Plugins
+            .add_plugins(
+                DefaultPlugins
+                    .set(log_plugin)
+                    // Window configuration
+                    .set(WindowPlugin {
+                        window: WindowDescriptor {
+                            title: format!(
+                                "{} v{}",
+                                env!("CARGO_PKG_NAME"),
+                                env!("CARGO_PKG_VERSION")
+                            ),
+                            resizable: true,
+                            ..default()
+                        },
                         ..default()
-                    },
-                    ..default()
-                }),
-        )
+                    }),
+            )
+            .add_plugin(EvoSimGuiPlugin)
+            // Spawn the camera and let it be moved around
+            .add_plugin(CameraControlPlugin)
+            // Color the tiles, with whichever overlay is selected
+            .add_plugin(MapOverlayPlugin)
+            // Let the world be edited by hand
+            .add_plugin(TileEditorPlugin);
+    }
+
+    // Run for a limited number of frames if requested
+    if let Some(frames) = config.frames {
+        app.insert_resource(NextState(SimulationMode::UntilFrame(frames)))
+            .insert_resource(NextState(SimulationState::Run));
+    }
+
+    // Load the map before anything else is done with the world
+    if let Some(path) = &config.map {
+        match map::load_map(path) {
+            Ok(simworld) => {
+                app.insert_resource(simworld);
+            }
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    app.insert_resource(config)
         .add_plugin(NetworkEcsPlugin)
         .add_plugin(SimWorldPlugin)
-        .add_plugin(EvoSimGuiPlugin)
-        // Spawn the camera and essential scene stuff
-        .add_startup_system(init_scene_system)
+        .add_plugin(SpatialIndexPlugin)
+        .add_plugin(SeasonPlugin)
+        .add_plugin(VisionPlugin)
+        .add_plugin(ScentPlugin)
+        .add_plugin(SignalPlugin)
+        .add_plugin(StatsPlugin)
+        .add_plugin(ExportPlugin)
+        // Spawn the Smittys once the world has been generated
+        .add_startup_system_to_stage(StartupStage::PostStartup, spawn_initial_smittys_system)
         // And go!
         .run();
 }
 
-/// Spawn the essentials into the scene.
-fn init_scene_system(mut commands: Commands, assets: Res<AssetServer>) {
-    // Spawn the camera
-    commands.spawn((Camera2dBundle {
-        transform: Transform::from_xyz(WORLD_SIZE.0 as f32 * 0.5, WORLD_SIZE.1 as f32 * 0.5, 900.0),
-        projection: OrthographicProjection {
-            scaling_mode: ScalingMode::Auto {
-                min_width: WORLD_SIZE.0 as f32,
-                min_height: WORLD_SIZE.1 as f32,
-            },
-            ..default()
-        },
-        ..default()
-    },));
+/// System to quit a headless simulation once it has stopped running, since
+/// nobody is around to start it again.
+fn exit_when_stopped_system(
+    sim_state: Res<CurrentState<SimulationState>>,
+    sim_time: Res<SimTime>,
+    stats: Res<SimStats>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if sim_state.0 == SimulationState::Stop {
+        info!(
+            "simulation stopped after {} world frames",
+            sim_time.world_frame
+        );
+        if let Some(latest) = stats.latest() {
+            info!(
+                "final population: {}, species: {}, mean energy: {:.3}",
+                latest.population, latest.species, latest.energy_mean
+            );
+        }
+        exit.send(AppExit);
+    }
+}
+
+/// Spawn the initial population of random Smittys onto passable tiles.
+fn spawn_initial_smittys_system(
+    mut commands: Commands,
+    assets: Option<Res<AssetServer>>,
+    simworld: Res<SimWorld>,
+    mut species: ResMut<SpeciesRegistry>,
+    mut rng: ResMut<SimRng>,
+) {
+    let rng = &mut rng.0;
+    let (w, h) = simworld.size();
+    // There are no assets to load when running headless
+    let texture = assets.map_or_else(default, |assets| assets.load("smitty.png"));
+
+    let mut spawned = 0;
+    // Give up eventually in case the world is mostly rock
+    for _ in 0..INITIAL_SMITTY_COUNT * 100 {
+        if spawned >= INITIAL_SMITTY_COUNT {
+            break;
+        }
 
-    // Spawn a sample Smitty
-    commands.spawn((SmittyBundle {
-        brain: SimEntityBrain::random(),
-        pos: SimEntityPosRot(
-            Vec2::new(WORLD_SIZE.0 as f32 / 2.0, WORLD_SIZE.1 as f32 / 2.0),
-            0.0,
-        ),
-        inputs: SimEntityBrainInputs {},
-        outputs: SimEntityBrainOutputs {
-            move_amt: 1.0,
-            rot_amt: 1.0,
-        },
-        traits: SimEntityTraits {
-            max_move_speed: SMITTY_MAX_MOVE_SPEED / 4.0,
-            max_rot_speed: SMITTY_MAX_ROT_SPEED / 4.0,
-        },
-        sprite: SpriteBundle {
-            transform: Transform::from_xyz(0.0, 0.0, 1.0).with_scale(Vec3::splat(SMITTY_SCALE)),
-            texture: assets.load("smitty.png"),
-            sprite: Sprite {
-                custom_size: Some(Vec2::splat(1.0)),
-                ..default()
-            },
-            ..default()
-        },
-    },));
+        let pos = Vec2::new(rng.gen_range(0.0..w as f32), rng.gen_range(0.0..h as f32));
+        let traits = SimEntityTraits::random(rng);
+        if simworld.is_passable(pos, traits.swim_ability) {
+            // Each founder starts its own species
+            commands.spawn(SmittyBundle::new(
+                SimEntityBrain::random(rng),
+                traits,
+                species.new_species(),
+                pos,
+                rng.gen_range(0.0..2.0 * PI),
+                texture.clone(),
+            ));
+            spawned += 1;
+        }
+    }
+    info!("spawned {} Smittys", spawned);
 }
@@ -0,0 +1,422 @@
+//! Saving and loading worlds as reusable map files.
+//!
+//! Map files are plain text: a `size <width> <height>` line, a
+//! `boundary <Torus|Walled|Deadly>` line, then a `tiles` line followed by one
+//! `<L|W|R> <food> <max food> <meat> <grazed frame>` line per tile, in rows
+//! from the bottom of the world to the top. The meat and grazed frame may be
+//! left off, for none. Blank lines and lines starting with `#` are ignored.
+//!
+//! Worlds can also be saved and loaded as PNG images (when the path ends in
+//! `.png`), with one pixel per tile: a red channel of at least half makes a
+//! rock tile, otherwise a blue channel of at least half makes a water tile,
+//! and anything else is land. The green channel is the tile's maximum food.
+//! The world size comes from the image size, and the boundary isn't stored.
+
+use crate::simworld::{SimTile, SimTileType, SimWorld, WorldBoundary, MAX_FOOD, MAX_MEAT};
+use bevy::render::{
+    render_resource::{Extent3d, TextureDimension, TextureFormat},
+    texture::{CompressedImageFormats, Image, ImageType},
+};
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+/// The most tiles a loaded map may have, so a bad size can't exhaust memory.
+pub const MAX_MAP_TILES: usize = 4096 * 4096;
+
+/// Possible errors returned when saving or loading a map.
+#[derive(Debug, thiserror::Error)]
+pub enum MapError {
+    #[error("failed to access map file: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("map file is missing its {0} line")]
+    MissingHeader(&'static str),
+
+    #[error("invalid map file on line {0}: {1}")]
+    InvalidLine(usize, String),
+
+    #[error("map file has {found} tiles but its size needs {expected}")]
+    WrongTileCount { expected: usize, found: usize },
+
+    #[error("failed to convert map image: {0}")]
+    Image(String),
+}
+
+/// Whether the given path is for a PNG image rather than a text map file.
+fn is_png(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("png"))
+}
+
+/// Save the world's tiles and boundary as a map file, or as an image if the
+/// path ends in `.png`.
+pub fn save_map(simworld: &SimWorld, path: &Path) -> Result<(), MapError> {
+    if is_png(path) {
+        return export_png(simworld, path);
+    }
+
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    let (w, h) = simworld.size();
+    writeln!(out, "# fafevosim map")?;
+    writeln!(out, "size {} {}", w, h)?;
+    writeln!(out, "boundary {:?}", simworld.boundary())?;
+    writeln!(out, "tiles")?;
+    for tile in simworld.tiles() {
+        let tile_type = match tile.tile_type {
+            SimTileType::Land => 'L',
+            SimTileType::Water => 'W',
+            SimTileType::Rock => 'R',
+        };
+        writeln!(
+            out,
+            "{} {} {} {} {}",
+            tile_type, tile.food, tile.max_food, tile.meat, tile.grazed_frame
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Load a world from a map file, or from an image if the path ends in `.png`.
+pub fn load_map(path: &Path) -> Result<SimWorld, MapError> {
+    if is_png(path) {
+        return import_png(path);
+    }
+
+    let text = fs::read_to_string(path)?;
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+    let invalid = |n: usize, line: &str| MapError::InvalidLine(n, line.to_owned());
+
+    // The header
+    let (n, line) = lines.next().ok_or(MapError::MissingHeader("size"))?;
+    let size = match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["size", w, h] => match (w.parse::<usize>(), h.parse::<usize>()) {
+            (Ok(w), Ok(h)) if w > 0 && h > 0 => (w, h),
+            _ => return Err(invalid(n, line)),
+        },
+        _ => return Err(MapError::MissingHeader("size")),
+    };
+    match size.0.checked_mul(size.1) {
+        Some(tiles) if tiles <= MAX_MAP_TILES => {}
+        _ => return Err(invalid(n, line)),
+    }
+    let (n, line) = lines.next().ok_or(MapError::MissingHeader("boundary"))?;
+    let boundary = match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["boundary", "Torus"] => WorldBoundary::Torus,
+        ["boundary", "Walled"] => WorldBoundary::Walled,
+        ["boundary", "Deadly"] => WorldBoundary::Deadly,
+        ["boundary", _] => return Err(invalid(n, line)),
+        _ => return Err(MapError::MissingHeader("boundary")),
+    };
+    match lines.next() {
+        Some((_, "tiles")) => {}
+        _ => return Err(MapError::MissingHeader("tiles")),
+    }
+
+    // The tiles, whose amounts of food can't be negative (or NaN)
+    let amount = |value: &str| {
+        value
+            .parse::<f32>()
+            .ok()
+            .filter(|amount| *amount >= 0.0 && amount.is_finite())
+    };
+    let mut tiles = Vec::with_capacity(size.0 * size.1);
+    for (n, line) in lines {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let (tile_type, food, max_food, meat, grazed_frame) = match fields[..] {
+            [tile_type, food, max_food] => (tile_type, food, max_food, "0", "0"),
+            [tile_type, food, max_food, meat, grazed_frame] => {
+                (tile_type, food, max_food, meat, grazed_frame)
+            }
+            _ => return Err(invalid(n, line)),
+        };
+        let tile_type = match tile_type {
+            "L" => SimTileType::Land,
+            "W" => SimTileType::Water,
+            "R" => SimTileType::Rock,
+            _ => return Err(invalid(n, line)),
+        };
+        let tile = match (
+            amount(food),
+            amount(max_food),
+            amount(meat),
+            grazed_frame.parse(),
+        ) {
+            (Some(food), Some(max_food), Some(meat), Ok(grazed_frame)) => SimTile {
+                tile_type,
+                food: food.min(max_food),
+                max_food,
+                meat: meat.min(MAX_MEAT),
+                grazed_frame,
+            },
+            _ => return Err(invalid(n, line)),
+        };
+        tiles.push(tile);
+    }
+    if tiles.len() != size.0 * size.1 {
+        return Err(MapError::WrongTileCount {
+            expected: size.0 * size.1,
+            found: tiles.len(),
+        });
+    }
+
+    let mut simworld = SimWorld::from_tiles(size, tiles);
+    simworld.set_boundary(boundary);
+    Ok(simworld)
+}
+
+/// Build a world from a PNG image, with one tile per pixel.
+pub fn import_png(path: &Path) -> Result<SimWorld, MapError> {
+    let bytes = fs::read(path)?;
+    let image = Image::from_buffer(
+        &bytes,
+        ImageType::Extension("png"),
+        CompressedImageFormats::NONE,
+        true,
+    )
+    .map_err(|err| MapError::Image(err.to_string()))?;
+    // Grayscale and RGB images need their channels spread out
+    let image = image
+        .convert(TextureFormat::Rgba8UnormSrgb)
+        .ok_or_else(|| MapError::Image("unsupported pixel format".to_owned()))?;
+
+    let size = image.texture_descriptor.size;
+    let (w, h) = (size.width as usize, size.height as usize);
+    if w == 0 || h == 0 {
+        return Err(MapError::Image("image is empty".to_owned()));
+    }
+    if w * h > MAX_MAP_TILES {
+        return Err(MapError::Image(format!("image is too large ({}x{})", w, h)));
+    }
+
+    // The image's rows go from top to bottom, unlike the world's
+    let mut tiles = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            let i = ((h - 1 - y) * w + x) * 4;
+            let [r, g, b, _] = [
+                image.data[i],
+                image.data[i + 1],
+                image.data[i + 2],
+                image.data[i + 3],
+            ];
+            let tile_type = if r >= 128 {
+                SimTileType::Rock
+            } else if b >= 128 {
+                SimTileType::Water
+            } else {
+                SimTileType::Land
+            };
+            // Nothing grows on rocks
+            let max_food = match tile_type {
+                SimTileType::Rock => 0.0,
+                _ => g as f32 / 255.0 * MAX_FOOD,
+            };
+            tiles.push(SimTile {
+                tile_type,
+                food: max_food,
+                max_food,
+                ..Default::default()
+            });
+        }
+    }
+    Ok(SimWorld::from_tiles((w, h), tiles))
+}
+
+/// Write the world out as a PNG image that `import_png` can read back in.
+pub fn export_png(simworld: &SimWorld, path: &Path) -> Result<(), MapError> {
+    let (w, h) = simworld.size();
+    let mut data = vec![0; w * h * 4];
+    for y in 0..h {
+        for x in 0..w {
+            let tile = simworld.tile((x, y)).unwrap();
+            let (r, b) = match tile.tile_type {
+                SimTileType::Land => (0, 0),
+                SimTileType::Water => (0, 255),
+                SimTileType::Rock => (255, 0),
+            };
+            let g = (tile.max_food / MAX_FOOD * 255.0).round().clamp(0.0, 255.0) as u8;
+            let i = ((h - 1 - y) * w + x) * 4;
+            data[i..i + 4].copy_from_slice(&[r, g, b, 255]);
+        }
+    }
+
+    let image = Image::new(
+        Extent3d {
+            width: w as u32,
+            height: h as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    image
+        .try_into_dynamic()
+        .map_err(|err| MapError::Image(err.to_string()))?
+        .save(path)
+        .map_err(|err| MapError::Image(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A path in the temporary directory for a test's map file, which is
+    /// unique to the test and this run of the tests.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fafevosim-{}-{}", std::process::id(), name))
+    }
+
+    /// Load a map file with the given text.
+    fn load_text(name: &str, text: &str) -> Result<SimWorld, MapError> {
+        let path = temp_path(name);
+        fs::write(&path, text).unwrap();
+        let result = load_map(&path);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn map_file_round_trip() {
+        let tiles = vec![
+            SimTile {
+                tile_type: SimTileType::Land,
+                food: 0.25,
+                max_food: 0.75,
+                meat: 0.5,
+                grazed_frame: 1234,
+            },
+            SimTile {
+                tile_type: SimTileType::Water,
+                food: 0.1,
+                max_food: 0.3,
+                meat: 0.0,
+                grazed_frame: 7,
+            },
+            SimTile {
+                tile_type: SimTileType::Rock,
+                ..Default::default()
+            },
+            SimTile {
+                tile_type: SimTileType::Land,
+                food: 1.0,
+                max_food: 1.0,
+                meat: 1.0 / 3.0,
+                grazed_frame: u32::MAX,
+            },
+        ];
+        let mut simworld = SimWorld::from_tiles((2, 2), tiles);
+        simworld.set_boundary(WorldBoundary::Walled);
+
+        let path = temp_path("round-trip.txt");
+        save_map(&simworld, &path).unwrap();
+        let loaded = load_map(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.size(), (2, 2));
+        assert_eq!(loaded.boundary(), WorldBoundary::Walled);
+        assert!(loaded.tiles().eq(simworld.tiles()));
+    }
+
+    #[test]
+    fn png_round_trip() {
+        // Maximum food is stored in steps of 1/255th of the most there can be
+        let step = |g: u8| g as f32 / 255.0 * MAX_FOOD;
+        let tile = |tile_type, max_food| SimTile {
+            tile_type,
+            food: 0.0,
+            max_food,
+            ..Default::default()
+        };
+        let tiles = vec![
+            tile(SimTileType::Land, step(0)),
+            tile(SimTileType::Land, step(255)),
+            tile(SimTileType::Water, step(100)),
+            tile(SimTileType::Rock, 0.0),
+            tile(SimTileType::Water, step(1)),
+            tile(SimTileType::Land, step(77)),
+        ];
+        let simworld = SimWorld::from_tiles((3, 2), tiles);
+
+        let path = temp_path("round-trip.png");
+        save_map(&simworld, &path).unwrap();
+        let loaded = load_map(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.size(), (3, 2));
+        for (loaded, saved) in loaded.tiles().zip(simworld.tiles()) {
+            assert_eq!(loaded.tile_type, saved.tile_type);
+            assert_eq!(loaded.max_food, saved.max_food);
+            // Images don't store the food, so tiles start out full
+            assert_eq!(loaded.food, saved.max_food);
+        }
+    }
+
+    #[test]
+    fn map_file_without_meat_or_grazing() {
+        let simworld =
+            load_text("short.txt", "size 1 1\nboundary Torus\ntiles\nL 0.5 0.25\n").unwrap();
+        let tile = simworld.tile((0, 0)).unwrap();
+        assert_eq!(tile.food, 0.25);
+        assert_eq!(tile.max_food, 0.25);
+        assert_eq!(tile.meat, 0.0);
+        assert_eq!(tile.grazed_frame, 0);
+    }
+
+    #[test]
+    fn map_file_rejects_bad_amounts() {
+        for (name, tile) in [
+            ("nan-food.txt", "L NaN 1 0 0"),
+            ("negative-food.txt", "L -0.5 1 0 0"),
+            ("nan-max-food.txt", "L 0.5 NaN 0 0"),
+            ("negative-max-food.txt", "W 0 -1 0 0"),
+            ("infinite-max-food.txt", "W 0 inf 0 0"),
+            ("nan-meat.txt", "L 0.5 1 NaN 0"),
+            ("negative-meat.txt", "L 0.5 1 -0.1 0"),
+            ("negative-grazed.txt", "L 0.5 1 0 -1"),
+            ("missing-grazed.txt", "L 0.5 1 0"),
+        ] {
+            let text = format!("size 1 1\nboundary Torus\ntiles\n{}\n", tile);
+            assert!(
+                matches!(load_text(name, &text), Err(MapError::InvalidLine(4, _))),
+                "{} loaded",
+                tile
+            );
+        }
+    }
+
+    #[test]
+    fn map_file_rejects_huge_sizes() {
+        for (name, size) in [
+            ("huge.txt", "size 4000000000 4000000000"),
+            ("overflowing.txt", "size 18446744073709551615 2"),
+            ("too-many-tiles.txt", "size 4097 4096"),
+        ] {
+            let text = format!("{}\nboundary Torus\ntiles\nL 0 0\n", size);
+            assert!(
+                matches!(load_text(name, &text), Err(MapError::InvalidLine(1, _))),
+                "{} loaded",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn map_file_meat_is_capped() {
+        let simworld = load_text(
+            "lots-of-meat.txt",
+            "size 1 1\nboundary Torus\ntiles\nL 0 0 1000 0\n",
+        )
+        .unwrap();
+        assert_eq!(simworld.tile((0, 0)).unwrap().meat, MAX_MEAT);
+    }
+}
@@ -100,7 +100,7 @@ impl<Float: NumFloat + SampleUniform + AddAssign> NN<Float> {
     /// layer size provided will be the number of inputs, the last will be
     /// the number of outputs.
     /// There must be at least two elements in this `layer_sizes` slice.
-    pub fn random(layers_sizes: &[u32]) -> Result<NN<Float>, NNCreateError> {
+    pub fn random(layers_sizes: &[u32], rng: &mut impl Rng) -> Result<NN<Float>, NNCreateError> {
         // Make sure there is at least an input layer and an output layer
         if layers_sizes.len() < 2 {
             return Err(NNCreateError::Min2Layers);
@@ -118,8 +118,6 @@ impl<Float: NumFloat + SampleUniform + AddAssign> NN<Float> {
         // get the first layer size
         let first_layer_size = *it.next().unwrap();
 
-        let mut rng = rand::thread_rng();
-
         // setup the rest of the layers
         let mut prev_layer_size = first_layer_size;
         for &layer_size in it {
@@ -153,13 +151,14 @@ impl<Float: NumFloat + SampleUniform + AddAssign> NN<Float> {
         activation: Activation,
         inputs: &[Float],
     ) -> Result<Vec<Float>, NNRunError> {
-        Ok(self.do_run(activation, inputs)?.pop().unwrap())
+        Ok(self.run_all(activation, inputs)?.pop().unwrap())
     }
 
-    /// Runs the neural network and returns all layer results.
+    /// Runs the neural network and returns the values of every layer, from the
+    /// inputs through to the outputs.
     /// Returns `Result::Err` when the input size does not match the number of
     /// inputs for this neural network.
-    fn do_run<Activation: ActivationFunction<Float>>(
+    pub fn run_all<Activation: ActivationFunction<Float>>(
         &self,
         activation: Activation,
         inputs: &[Float],
@@ -199,6 +198,20 @@ impl<Float: NumFloat + SampleUniform + AddAssign> NN<Float> {
         }
     }
 
+    /// Randomly nudge the weights of the network, each with a `rate` chance of
+    /// changing by up to `strength` in either direction.
+    pub fn mutate(&mut self, rate: f64, strength: Float, rng: &mut impl Rng) {
+        for layer in self.layers.iter_mut() {
+            for node in layer.iter_mut() {
+                for weight in node.iter_mut() {
+                    if rng.gen_bool(rate) {
+                        *weight += rng.gen_range(-strength..=strength);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn layers(&self) -> &[NNLayer<Float>] {
         &self.layers
     }
@@ -0,0 +1,358 @@
+//! Alternative ways of coloring the world's tiles to show what's going on.
+
+use crate::{
+    ecs::{SimEntityPosRot, SimEntitySpecies, SimEntityTraits, SimTime, FRAME_DELTA_SECONDS},
+    gui::SelectedSmitty,
+    scent::{ScentChannel, ScentFields},
+    simworld::{
+        world_texture_image, DirtyTileTracker, SimTile, SimTileType, SimWorld, WorldTextureMarker,
+        WATER_MIN_SPEED_MULT,
+    },
+};
+use bevy::prelude::*;
+
+/// The number of Smittys on a tile shown as the hottest density.
+const DENSITY_OVERLAY_MAX: f32 = 4.0;
+/// The time (in seconds) since a tile was grazed shown as the oldest food.
+const FOOD_AGE_OVERLAY_MAX: f32 = 60.0;
+/// The swim ability used for the movement cost overlay when no Smitty is
+/// selected.
+const DEFAULT_OVERLAY_SWIM_ABILITY: f32 = 0.5;
+/// The scent strength shown as the hottest.
+const SCENT_OVERLAY_MAX: f32 = 1.0;
+/// The weakest scent shown at all.
+const SCENT_OVERLAY_MIN: f32 = 0.001;
+/// The color of tiles that an overlay has nothing to show for.
+const OVERLAY_EMPTY_COLOR: Color = Color::rgb(0.05, 0.05, 0.05);
+/// The color of rock tiles in overlays about food.
+const OVERLAY_ROCK_COLOR: Color = Color::rgb(0.3, 0.3, 0.3);
+
+/// Plugin that colors the world texture according to the selected overlay.
+pub struct MapOverlayPlugin;
+
+impl Plugin for MapOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // Add the overlay selection and the data it needs
+            .init_resource::<MapOverlay>()
+            .init_resource::<OverlayData>()
+            // Only recolor once per rendered frame
+            .add_system_to_stage(CoreStage::PostUpdate, update_overlay_data_system)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                update_tile_color_system.after(update_overlay_data_system),
+            );
+    }
+}
+
+/// Resource containing the way the tiles are currently colored.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Resource)]
+pub enum MapOverlay {
+    /// The tile type, lightened by food and tinted by meat.
+    #[default]
+    Terrain,
+    /// How full of food each tile is compared to its maximum.
+    FoodRatio,
+    /// The number of Smittys standing on each tile.
+    Density,
+    /// The most common species standing on each tile.
+    Species,
+    /// How long it's been since each tile was last grazed.
+    FoodAge,
+    /// How slow each tile is to cross.
+    MoveCost,
+    /// How strong a channel of scent is on each tile.
+    Scent(ScentChannel),
+}
+
+impl MapOverlay {
+    /// All of the overlays, in the order they're listed.
+    pub const ALL: [Self; 9] = [
+        Self::Terrain,
+        Self::FoodRatio,
+        Self::Density,
+        Self::Species,
+        Self::FoodAge,
+        Self::MoveCost,
+        Self::Scent(ScentChannel::Food),
+        Self::Scent(ScentChannel::PheromoneA),
+        Self::Scent(ScentChannel::PheromoneB),
+    ];
+
+    /// Get the name of this overlay to show the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Terrain => "Terrain",
+            Self::FoodRatio => "Food ratio",
+            Self::Density => "Smitty density",
+            Self::Species => "Dominant species",
+            Self::FoodAge => "Food age",
+            Self::MoveCost => "Movement cost",
+            Self::Scent(channel) => channel.name(),
+        }
+    }
+
+    /// Get the colors used by this overlay and what each of them means.
+    pub fn legend(&self) -> Vec<(Color, String)> {
+        match self {
+            Self::Terrain => vec![
+                (
+                    tile_color(SimTileType::Land, 1.0),
+                    "Land (lighter with food)".into(),
+                ),
+                (
+                    tile_color(SimTileType::Water, 1.0),
+                    "Water (lighter with food)".into(),
+                ),
+                (tile_color(SimTileType::Rock, 0.0), "Rock".into()),
+                (
+                    SimTile {
+                        meat: 1.0,
+                        ..default()
+                    }
+                    .color(),
+                    "Meat".into(),
+                ),
+            ],
+            Self::FoodRatio => vec![
+                (heat(0.0), "Empty".into()),
+                (heat(0.5), "Half grown".into()),
+                (heat(1.0), "Fully grown".into()),
+                (OVERLAY_ROCK_COLOR, "Rock".into()),
+            ],
+            Self::Density => vec![
+                (OVERLAY_EMPTY_COLOR, "No Smittys".into()),
+                (heat(1.0 / DENSITY_OVERLAY_MAX), "1 Smitty".into()),
+                (
+                    heat(1.0),
+                    format!("{} or more Smittys", DENSITY_OVERLAY_MAX),
+                ),
+            ],
+            Self::Species => vec![
+                (OVERLAY_EMPTY_COLOR, "No Smittys".into()),
+                (species_color(0), "Each species has its own hue".into()),
+            ],
+            Self::FoodAge => vec![
+                (heat(0.0), "Just grazed".into()),
+                (
+                    heat(0.5),
+                    format!("{}s since grazed", FOOD_AGE_OVERLAY_MAX * 0.5),
+                ),
+                (heat(1.0), format!("{}s or more", FOOD_AGE_OVERLAY_MAX)),
+                (OVERLAY_ROCK_COLOR, "Rock".into()),
+            ],
+            Self::MoveCost => vec![
+                (heat(0.0), "Full speed".into()),
+                (heat(1.0), format!("{}× slower", 1.0 / WATER_MIN_SPEED_MULT)),
+                (OVERLAY_EMPTY_COLOR, "Impassable".into()),
+            ],
+            Self::Scent(_) => vec![
+                (OVERLAY_EMPTY_COLOR, "None".into()),
+                (heat(0.0), "Faint".into()),
+                (heat(1.0), format!("{} or stronger", SCENT_OVERLAY_MAX)),
+            ],
+        }
+    }
+
+    /// Whether this overlay can change without the tiles changing, so every
+    /// tile has to be redrawn each frame rather than just the changed ones.
+    fn redraws_every_frame(&self) -> bool {
+        !matches!(self, Self::Terrain | Self::FoodRatio)
+    }
+
+    /// Get the color of the tile at the given index with this overlay.
+    fn color(&self, tile: &SimTile, data: &OverlayData, i: usize) -> Color {
+        match self {
+            Self::Terrain => tile.color(),
+            Self::FoodRatio if tile.max_food > 0.0 => heat(tile.food / tile.max_food),
+            Self::FoodAge if tile.max_food > 0.0 => {
+                let age =
+                    data.world_frame.saturating_sub(tile.grazed_frame) as f32 * FRAME_DELTA_SECONDS;
+                heat(age / FOOD_AGE_OVERLAY_MAX)
+            }
+            Self::FoodRatio | Self::FoodAge => OVERLAY_ROCK_COLOR,
+            Self::Density => match data.density[i] {
+                0 => OVERLAY_EMPTY_COLOR,
+                n => heat(n as f32 / DENSITY_OVERLAY_MAX),
+            },
+            Self::Species => data.species[i].map_or(OVERLAY_EMPTY_COLOR, species_color),
+            Self::MoveCost => match tile.tile_type.move_speed_mult(data.swim_ability) {
+                // Scale the cost so the slowest possible tile is the hottest
+                Some(mult) => heat((1.0 / mult - 1.0) / (1.0 / WATER_MIN_SPEED_MULT - 1.0)),
+                None => OVERLAY_EMPTY_COLOR,
+            },
+            Self::Scent(_) => match data.scent.get(i) {
+                Some(&level) if level >= SCENT_OVERLAY_MIN => heat(level / SCENT_OVERLAY_MAX),
+                _ => OVERLAY_EMPTY_COLOR,
+            },
+        }
+    }
+}
+
+/// Resource containing the data about the Smittys on each tile needed by the
+/// current overlay.
+#[derive(Default, Resource)]
+struct OverlayData {
+    /// The number of Smittys on each tile.
+    density: Vec<u32>,
+    /// The most common species on each tile.
+    species: Vec<Option<u32>>,
+    /// The swim ability to show the movement cost for.
+    swim_ability: f32,
+    /// The current world frame, to work out how old the food is.
+    world_frame: u32,
+    /// The strength of the shown scent channel on each tile.
+    scent: Vec<f32>,
+}
+
+/// Color from blue (0.0) through green to red (1.0).
+fn heat(t: f32) -> Color {
+    Color::hsl(240.0 * (1.0 - t.clamp(0.0, 1.0)), 0.9, 0.5)
+}
+
+/// A distinct color for each species, spreading their hues around the wheel.
+fn species_color(species: u32) -> Color {
+    Color::hsl((species as f32 * 137.508) % 360.0, 0.8, 0.55)
+}
+
+/// The terrain color of a tile of the given type and food.
+fn tile_color(tile_type: SimTileType, food: f32) -> Color {
+    SimTile {
+        tile_type,
+        food,
+        ..default()
+    }
+    .color()
+}
+
+/// System to count up the Smittys on each tile, if the overlay needs it.
+fn update_overlay_data_system(
+    overlay: Res<MapOverlay>,
+    simworld: Res<SimWorld>,
+    sim_time: Res<SimTime>,
+    selected_smitty: Res<SelectedSmitty>,
+    scent: Res<ScentFields>,
+    mut data: ResMut<OverlayData>,
+    smittys: Query<(&SimEntityPosRot, &SimEntitySpecies)>,
+    traits: Query<&SimEntityTraits>,
+    mut tile_species: Local<Vec<(usize, u32)>>,
+) {
+    let (w, h) = simworld.size();
+    let tile_index = |pos: Vec2| {
+        let tile = pos.floor().as_ivec2();
+        (tile.x >= 0 && tile.y >= 0 && (tile.x as usize) < w && (tile.y as usize) < h)
+            .then(|| tile.y as usize * w + tile.x as usize)
+    };
+
+    data.world_frame = sim_time.world_frame;
+    match *overlay {
+        MapOverlay::Density => {
+            data.density.clear();
+            data.density.resize(w * h, 0);
+            for (pos, _) in smittys.iter() {
+                if let Some(i) = tile_index(pos.0) {
+                    data.density[i] += 1;
+                }
+            }
+        }
+        MapOverlay::Species => {
+            // Sort the Smittys by tile and then species, so each species on a
+            // tile is one run
+            tile_species.clear();
+            tile_species.extend(
+                smittys
+                    .iter()
+                    .filter_map(|(pos, species)| Some((tile_index(pos.0)?, species.0))),
+            );
+            tile_species.sort_unstable();
+
+            data.species.clear();
+            data.species.resize(w * h, None);
+            let mut most = (usize::MAX, 0);
+            let mut start = 0;
+            while start < tile_species.len() {
+                let (tile, species) = tile_species[start];
+                let count = tile_species[start..]
+                    .iter()
+                    .take_while(|&&pair| pair == (tile, species))
+                    .count();
+                // The oldest species on a tile comes first, so it wins ties
+                // and the colors don't flicker
+                if tile != most.0 || count > most.1 {
+                    data.species[tile] = Some(species);
+                    most = (tile, count);
+                }
+                start += count;
+            }
+        }
+        MapOverlay::MoveCost => {
+            // Show the costs for the selected Smitty if there is one
+            data.swim_ability = selected_smitty
+                .0
+                .and_then(|selected| traits.get(selected).ok())
+                .map_or(DEFAULT_OVERLAY_SWIM_ABILITY, |traits| traits.swim_ability);
+        }
+        MapOverlay::Scent(channel) => {
+            data.scent.clear();
+            data.scent.extend_from_slice(scent.field(channel));
+        }
+        _ => {}
+    }
+}
+
+/// System to draw each tile into the world texture with the current overlay.
+fn update_tile_color_system(
+    overlay: Res<MapOverlay>,
+    mut simworld: ResMut<SimWorld>,
+    data: Res<OverlayData>,
+    mut images: ResMut<Assets<Image>>,
+    mut world_sprite: Query<(&Handle<Image>, &mut Sprite), With<WorldTextureMarker>>,
+    mut tracker: Local<Option<DirtyTileTracker>>,
+    mut drawn_overlay: Local<Option<MapOverlay>>,
+) {
+    let tracker = *tracker.get_or_insert_with(|| simworld.track_dirty_tiles());
+    let dirty = simworld.take_dirty_tiles(tracker);
+    let (w, h) = simworld.size();
+    let size = Vec2::new(w as f32, h as f32);
+
+    // Don't touch the image (and upload it again) unless something changed
+    let (handle, mut sprite) = world_sprite.single_mut();
+    let full_redraw = overlay.redraws_every_frame()
+        || *drawn_overlay != Some(*overlay)
+        || sprite.custom_size != Some(size);
+    if dirty.is_empty() && !full_redraw {
+        return;
+    }
+    let image = match images.get_mut(handle) {
+        Some(image) => image,
+        None => return,
+    };
+    *drawn_overlay = Some(*overlay);
+
+    // Start over if the world has changed size
+    if image.size() != size {
+        *image = world_texture_image((w, h));
+        sprite.custom_size = Some(size);
+    }
+
+    let mut draw = |x: usize, y: usize| {
+        let tile = simworld.tile((x, y)).unwrap();
+        let color = overlay.color(&tile, &data, y * w + x).as_rgba_f32();
+        // The texture's rows go from top to bottom, unlike the world's
+        let i = ((h - 1 - y) * w + x) * 4;
+        for (texel, channel) in image.data[i..i + 4].iter_mut().zip(color) {
+            *texel = (channel.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    };
+    if full_redraw {
+        for y in 0..h {
+            for x in 0..w {
+                draw(x, y);
+            }
+        }
+    } else {
+        for (x, y) in dirty {
+            draw(x, y);
+        }
+    }
+}
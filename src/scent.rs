@@ -0,0 +1,296 @@
+//! Scalar fields of scent spread across the world, which diffuse and fade
+//! over time. Food gives off its own scent, and Smittys can lay down
+//! pheromones to leave trails for each other.
+
+use crate::{
+    ecs::{
+        is_neural_update_frame_system, move_smittys_system, FrameUpdateStage, NeuralUpdateStage,
+        SimEntityBrainInputs, SimEntityPosRot, SimulationAppExt, SimulationState, SmittyActions,
+        FRAME_DELTA_SECONDS,
+    },
+    simworld::{SimWorld, WorldBoundary},
+};
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+/// The scent per second given off by a tile full of food (or meat).
+pub const FOOD_SCENT_EMIT_RATE: f32 = 0.5;
+/// The pheromone per second laid down by a Smitty depositing at full
+/// strength.
+pub const PHEROMONE_DEPOSIT_RATE: f32 = 1.0;
+/// The energy per second it costs a Smitty to deposit pheromone at full
+/// strength.
+pub const SMITTY_PHEROMONE_DRAIN: f32 = 0.002;
+/// How strongly scent gradients are scaled before being squashed into brain
+/// inputs.
+const SCENT_GRADIENT_GAIN: f32 = 4.0;
+
+/// Plugin that keeps the scent fields spreading and lets Smittys smell them.
+pub struct ScentPlugin;
+
+impl Plugin for ScentPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // Add the fields
+            .init_resource::<ScentFields>()
+            // Lay down scent as things move, then spread it once they're done
+            .add_simulation_system(
+                FrameUpdateStage::UpdateEntities,
+                deposit_scent_system
+                    .run_in_state(SimulationState::Run)
+                    .after(move_smittys_system),
+            )
+            .add_simulation_system(
+                FrameUpdateStage::Cleanup,
+                spread_scent_system.run_in_state(SimulationState::Run),
+            )
+            // Smell before each neural update
+            .add_simulation_system(
+                NeuralUpdateStage::Collect,
+                collect_scent_system
+                    .run_in_state(SimulationState::Run)
+                    .run_if(is_neural_update_frame_system),
+            );
+    }
+}
+
+/// The kinds of scent spread across the world.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ScentChannel {
+    /// Given off by plant food and meat.
+    Food,
+    /// Laid down by Smittys, for whatever they evolve to use it for.
+    PheromoneA,
+    /// A second pheromone, so different messages can be left.
+    PheromoneB,
+}
+
+impl ScentChannel {
+    /// All of the channels, in the order their brain inputs are fed in.
+    pub const ALL: [Self; 3] = [Self::Food, Self::PheromoneA, Self::PheromoneB];
+    /// The number of channels.
+    pub const COUNT: usize = Self::ALL.len();
+    /// The channels Smittys can deposit into, in the order of their actions.
+    pub const PHEROMONES: [Self; 2] = [Self::PheromoneA, Self::PheromoneB];
+
+    /// Get the name of this channel to show the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Food => "Food scent",
+            Self::PheromoneA => "Pheromone A",
+            Self::PheromoneB => "Pheromone B",
+        }
+    }
+
+    /// Get how fast (in tiles squared per second) this scent spreads out.
+    pub fn diffusion_rate(&self) -> f32 {
+        match self {
+            Self::Food => 0.5,
+            Self::PheromoneA | Self::PheromoneB => 0.1,
+        }
+    }
+
+    /// Get the portion of this scent that fades away each second.
+    pub fn decay_rate(&self) -> f32 {
+        match self {
+            Self::Food => 0.5,
+            Self::PheromoneA | Self::PheromoneB => 0.05,
+        }
+    }
+}
+
+/// What a Smitty smells of a single scent channel.
+#[derive(Default, Debug, Copy, Clone)]
+pub struct ScentSense {
+    /// How strong the scent is underfoot, from 0.0 (none) towards 1.0.
+    pub level: f32,
+    /// How much stronger the scent gets ahead of the Smitty, from -1.0 to 1.0.
+    pub forward: f32,
+    /// How much stronger the scent gets to the Smitty's left, from -1.0 to
+    /// 1.0.
+    pub left: f32,
+}
+
+impl ScentSense {
+    /// The number of brain inputs for each channel.
+    pub const COUNT: usize = 3;
+    /// The names of each channel's inputs, in the order they're fed into the
+    /// brain.
+    pub const NAMES: [&'static str; Self::COUNT] = ["level", "forward", "left"];
+
+    /// Get the channel's inputs in the order they're fed into the brain.
+    pub fn to_array(&self) -> [f32; Self::COUNT] {
+        [self.level, self.forward, self.left]
+    }
+}
+
+/// Resource containing one scalar field per scent channel, with a value for
+/// each of the world's tiles.
+#[derive(Default, Resource)]
+pub struct ScentFields {
+    fields: [Vec<f32>; ScentChannel::COUNT],
+    /// Spare field written to while spreading, so nothing is allocated each
+    /// tick.
+    scratch: Vec<f32>,
+    size: (usize, usize),
+    boundary: WorldBoundary,
+}
+
+impl ScentFields {
+    /// Clear the fields if the world has changed size.
+    fn fit_to(&mut self, simworld: &SimWorld) {
+        let size = simworld.size();
+        if self.size != size {
+            for field in self.fields.iter_mut() {
+                *field = vec![0.0; size.0 * size.1];
+            }
+            self.scratch = vec![0.0; size.0 * size.1];
+            self.size = size;
+        }
+        self.boundary = simworld.boundary();
+    }
+
+    /// Get the values of a channel's field, one per tile in the same order as
+    /// the world's tiles.
+    pub fn field(&self, channel: ScentChannel) -> &[f32] {
+        &self.fields[channel as usize]
+    }
+
+    /// Get the strength of a channel's scent on the tile containing the given
+    /// position, or 0.0 if it's out of world bounds.
+    pub fn level_at(&self, channel: ScentChannel, pos: Vec2) -> f32 {
+        self.tile_index(pos.floor().as_ivec2())
+            .map_or(0.0, |i| self.fields[channel as usize][i])
+    }
+
+    /// Get the direction and rate the strength of a channel's scent increases
+    /// at the tile containing the given position. The scent is taken to be
+    /// flat across the edges of worlds that aren't toroidal.
+    pub fn gradient_at(&self, channel: ScentChannel, pos: Vec2) -> Vec2 {
+        let tile = pos.floor().as_ivec2();
+        let center = self.level_at(channel, pos);
+        let level = |offset: IVec2| {
+            self.tile_index(tile + offset)
+                .map_or(center, |i| self.fields[channel as usize][i])
+        };
+        Vec2::new(
+            level(IVec2::X) - level(-IVec2::X),
+            level(IVec2::Y) - level(-IVec2::Y),
+        ) * 0.5
+    }
+
+    /// Add scent to the tile containing the given position.
+    fn deposit(&mut self, channel: ScentChannel, pos: Vec2, amount: f32) {
+        if let Some(i) = self.tile_index(pos.floor().as_ivec2()) {
+            self.fields[channel as usize][i] += amount;
+        }
+    }
+
+    /// Get the index of a tile, wrapping it around a toroidal world, or `None`
+    /// if it's out of world bounds.
+    fn tile_index(&self, tile: IVec2) -> Option<usize> {
+        let (w, h) = (self.size.0 as i32, self.size.1 as i32);
+        let tile = match self.boundary {
+            WorldBoundary::Torus if w > 0 && h > 0 => {
+                IVec2::new(tile.x.rem_euclid(w), tile.y.rem_euclid(h))
+            }
+            _ => tile,
+        };
+        if tile.x >= 0 && tile.y >= 0 && tile.x < w && tile.y < h {
+            Some(tile.y as usize * self.size.0 + tile.x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Spread each field out to the neighbouring tiles and let it fade, over
+    /// one world frame.
+    fn spread(&mut self) {
+        let (w, h) = self.size;
+        let torus = self.boundary == WorldBoundary::Torus;
+        // Scent bounces off the edges of worlds that aren't toroidal
+        let prev = |v: usize, max: usize| match v {
+            0 if torus => max - 1,
+            0 => 0,
+            v => v - 1,
+        };
+        let next = |v: usize, max: usize| match v + 1 {
+            v if v < max => v,
+            _ if torus => 0,
+            _ => max - 1,
+        };
+
+        for channel in ScentChannel::ALL {
+            let diffusion = channel.diffusion_rate() * FRAME_DELTA_SECONDS;
+            let keep = 1.0 - channel.decay_rate() * FRAME_DELTA_SECONDS;
+            let field = &self.fields[channel as usize];
+            for y in 0..h {
+                let (up, down) = (next(y, h) * w, prev(y, h) * w);
+                let row = y * w;
+                for x in 0..w {
+                    let value = field[row + x];
+                    let neighbours = field[row + prev(x, w)]
+                        + field[row + next(x, w)]
+                        + field[up + x]
+                        + field[down + x];
+                    self.scratch[row + x] = (value + diffusion * (neighbours - 4.0 * value)) * keep;
+                }
+            }
+            std::mem::swap(&mut self.fields[channel as usize], &mut self.scratch);
+        }
+    }
+}
+
+/// System to let food give off its scent and Smittys lay down the pheromones
+/// they chose to.
+fn deposit_scent_system(
+    simworld: Res<SimWorld>,
+    mut scent: ResMut<ScentFields>,
+    query: Query<(&SimEntityPosRot, &SmittyActions)>,
+) {
+    scent.fit_to(&simworld);
+
+    let emit = FOOD_SCENT_EMIT_RATE * FRAME_DELTA_SECONDS;
+    let food_field = &mut scent.fields[ScentChannel::Food as usize];
+    for (value, tile) in food_field.iter_mut().zip(simworld.tiles()) {
+        *value += (tile.food + tile.meat) * emit;
+    }
+
+    for (pos, actions) in query.iter() {
+        for (channel, &amount) in ScentChannel::PHEROMONES.iter().zip(&actions.pheromones) {
+            if amount > 0.0 {
+                scent.deposit(
+                    *channel,
+                    pos.0,
+                    amount * PHEROMONE_DEPOSIT_RATE * FRAME_DELTA_SECONDS,
+                );
+            }
+        }
+    }
+}
+
+/// System to spread the scent out and let it fade each world frame.
+fn spread_scent_system(simworld: Res<SimWorld>, mut scent: ResMut<ScentFields>) {
+    scent.fit_to(&simworld);
+    scent.spread();
+}
+
+/// System to let the Smittys smell the scent underfoot and which way it gets
+/// stronger, relative to their heading.
+fn collect_scent_system(
+    scent: Res<ScentFields>,
+    mut query: Query<(&SimEntityPosRot, &mut SimEntityBrainInputs)>,
+) {
+    for (pos, mut inputs) in query.iter_mut() {
+        let forward = Vec2::new(pos.1.cos(), pos.1.sin());
+        let left = forward.perp();
+        for (channel, sense) in ScentChannel::ALL.iter().zip(inputs.scent.iter_mut()) {
+            let gradient = scent.gradient_at(*channel, pos.0) * SCENT_GRADIENT_GAIN;
+            *sense = ScentSense {
+                level: scent.level_at(*channel, pos.0).tanh(),
+                forward: gradient.dot(forward).tanh(),
+                left: gradient.dot(left).tanh(),
+            };
+        }
+    }
+}
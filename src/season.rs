@@ -0,0 +1,223 @@
+//! The turning of the seasons, which warm and cool the world, speed up and
+//! slow down plant growth, and raise and lower the water.
+
+use crate::{
+    config::SimConfig,
+    ecs::{
+        update_simulation_time_system, FrameUpdateStage, SimEntityTraits, SimTime,
+        SimulationAppExt, SimulationState, FRAME_DELTA_SECONDS,
+    },
+    simworld::{DirtyTileTracker, SimTileType, SimWorld, WorldBoundary},
+};
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use std::{collections::VecDeque, f32::consts::TAU};
+
+/// How far plant growth swings either way from its usual rate over a year,
+/// as a portion of that rate.
+pub const SEASON_FOOD_GROWTH_SWING: f32 = 0.8;
+/// The energy per second drained from a Smitty of mass 1.0 to keep warm in
+/// the depths of winter. Lighter Smittys lose their heat faster.
+pub const SMITTY_COLD_ENERGY_DRAIN: f32 = 0.003;
+
+/// Plugin that moves the world through the seasons.
+pub struct SeasonPlugin;
+
+impl Plugin for SeasonPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // Add the season resource
+            .init_resource::<Season>()
+            // Change the season as soon as the frame is ticked
+            .add_simulation_system(
+                FrameUpdateStage::UpdateTiming,
+                update_season_system
+                    .run_in_state(SimulationState::Run)
+                    .after(update_simulation_time_system),
+            );
+    }
+}
+
+/// Resource containing the current season, and how the water rises and falls
+/// across the world's tiles with it.
+#[derive(Default, Resource)]
+pub struct Season {
+    /// How far through the year the world is, from 0.0 (the middle of
+    /// spring) up to 1.0.
+    phase: f32,
+    /// How many tiles the water has spread past (or drawn back from) its usual
+    /// shoreline, which it's at in the middle of spring and autumn.
+    water_level: i32,
+    /// The height of each tile in tiles above the shoreline, or `None` for
+    /// tiles the water never reaches or leaves. A tile is underwater whenever
+    /// its height is at most the water level.
+    elevation: Vec<Option<i32>>,
+    /// The tile types as the seasons last left them, to tell which changed
+    /// tiles something else has changed the type of.
+    tile_types: Vec<SimTileType>,
+}
+
+impl Season {
+    /// The names of the seasons, in the order they come. Each is centred on a
+    /// quarter of the year, starting with spring on 0.0.
+    const NAMES: [&'static str; 4] = ["Spring", "Summer", "Autumn", "Winter"];
+
+    /// Get how far through the year the world is, from 0.0 (the middle of
+    /// spring) up to 1.0.
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+
+    /// Get the name of the current season to show the user.
+    pub fn name(&self) -> &'static str {
+        Self::NAMES[(self.phase * 4.0 + 0.5) as usize % Self::NAMES.len()]
+    }
+
+    /// Get how warm it is, from -1.0 (midwinter) to 1.0 (midsummer).
+    pub fn temperature(&self) -> f32 {
+        (self.phase * TAU).sin()
+    }
+
+    /// Get whether the world is warming up or cooling down, from -1.0 (in the
+    /// middle of autumn) to 1.0 (in the middle of spring).
+    pub fn trend(&self) -> f32 {
+        (self.phase * TAU).cos()
+    }
+
+    /// Get the multiplier applied to how fast plant food grows back, which
+    /// is highest in the summer.
+    pub fn food_growth_mult(&self) -> f32 {
+        1.0 + SEASON_FOOD_GROWTH_SWING * self.temperature()
+    }
+
+    /// Get how many tiles the water has spread past (or, when negative, drawn
+    /// back from) its usual shoreline.
+    pub fn water_level(&self) -> i32 {
+        self.water_level
+    }
+
+    /// Get the energy per second a Smitty spends keeping warm in the cold.
+    pub fn cold_energy_drain(&self, traits: &SimEntityTraits) -> f32 {
+        // Heat is lost through the skin, which lighter bodies have more of for
+        // their mass
+        SMITTY_COLD_ENERGY_DRAIN * (-self.temperature()).max(0.0) / traits.mass().cbrt()
+    }
+
+    /// Work out the tiles' heights again if something other than the seasons
+    /// has changed the type of any of the given changed tiles.
+    fn fit_to(&mut self, simworld: &SimWorld, dirty: &[(usize, usize)]) {
+        let (w, h) = simworld.size();
+        let unchanged = self.tile_types.len() == w * h
+            && dirty.iter().all(|&pos| {
+                simworld.tile(pos).map(|tile| tile.tile_type)
+                    == Some(self.tile_types[pos.1 * w + pos.0])
+            });
+        if unchanged {
+            return;
+        }
+        self.tile_types = simworld.tiles().map(|tile| tile.tile_type).collect();
+
+        // Walk outwards from the shoreline, with the land getting higher and
+        // the water getting deeper the farther they are from it
+        let torus = simworld.boundary() == WorldBoundary::Torus;
+        let neighbours = |i: usize| {
+            let (x, y) = ((i % w) as i32, (i / w) as i32);
+            [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                .into_iter()
+                .filter_map(move |(dx, dy)| {
+                    let (mut nx, mut ny) = (x + dx, y + dy);
+                    if torus {
+                        nx = nx.rem_euclid(w as i32);
+                        ny = ny.rem_euclid(h as i32);
+                    }
+                    (nx >= 0 && ny >= 0 && nx < w as i32 && ny < h as i32)
+                        .then(|| ny as usize * w + nx as usize)
+                })
+        };
+        let types = &self.tile_types;
+        let mut elevation = vec![None; types.len()];
+        let mut queue = VecDeque::new();
+        for (i, &tile_type) in types.iter().enumerate() {
+            let (height, shore_type) = match tile_type {
+                SimTileType::Land => (1, SimTileType::Water),
+                SimTileType::Water => (0, SimTileType::Land),
+                SimTileType::Rock => continue,
+            };
+            if neighbours(i).any(|n| types[n] == shore_type) {
+                elevation[i] = Some(height);
+                queue.push_back(i);
+            }
+        }
+        while let Some(i) = queue.pop_front() {
+            let height = elevation[i].unwrap();
+            let step = match types[i] {
+                SimTileType::Land => 1,
+                _ => -1,
+            };
+            for n in neighbours(i) {
+                if types[n] == types[i] && elevation[n].is_none() {
+                    elevation[n] = Some(height + step);
+                    queue.push_back(n);
+                }
+            }
+        }
+
+        // The shoreline found is where the water is now, not where it was at
+        // the start of the year
+        self.elevation = elevation
+            .into_iter()
+            .map(|height| height.map(|height| height + self.water_level))
+            .collect();
+    }
+
+    /// Raise or lower the water to the given level, flooding or draining the
+    /// tiles in between.
+    fn set_water_level(&mut self, simworld: &mut SimWorld, level: i32) {
+        self.water_level = level;
+        let (w, _) = simworld.size();
+        for (i, height) in self.elevation.iter().enumerate() {
+            let height = match height {
+                Some(height) => *height,
+                None => continue,
+            };
+            let tile_type = if height <= level {
+                SimTileType::Water
+            } else {
+                SimTileType::Land
+            };
+            if self.tile_types[i] != tile_type {
+                self.tile_types[i] = tile_type;
+                if let Some(mut tile) = simworld.tile_mut((i % w, i / w)) {
+                    tile.tile_type = tile_type;
+                }
+            }
+        }
+    }
+}
+
+/// System to move the world on through the year, raising or lowering the
+/// water when its level changes.
+fn update_season_system(
+    sim_time: Res<SimTime>,
+    config: Res<SimConfig>,
+    mut season: ResMut<Season>,
+    mut simworld: ResMut<SimWorld>,
+    mut tracker: Local<Option<DirtyTileTracker>>,
+) {
+    let tracker = *tracker.get_or_insert_with(|| simworld.track_dirty_tiles());
+    let dirty = simworld.take_dirty_tiles(tracker);
+
+    season.phase = if config.season_period > 0.0 {
+        let seconds = sim_time.world_frame as f64 * FRAME_DELTA_SECONDS as f64;
+        (seconds / config.season_period as f64).fract() as f32
+    } else {
+        0.0
+    };
+
+    // The water is highest in the winter and lowest in the summer
+    let level = (-season.temperature() * config.flood_range as f32).round() as i32;
+    season.fit_to(&simworld, &dirty);
+    if level != season.water_level {
+        season.set_water_level(&mut simworld, level);
+    }
+}
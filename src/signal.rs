@@ -0,0 +1,123 @@
+//! Signals Smittys give off for the Smittys around them to hear, so they can
+//! evolve to communicate.
+
+use crate::{
+    ecs::{
+        is_neural_update_frame_system, NeuralUpdateStage, SimEntityBrainInputs, SimEntityPosRot,
+        SimEntityTraits, SimulationAppExt, SimulationState, SmittyActions,
+    },
+    simworld::SimWorld,
+    spatial::SpatialIndex,
+};
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use std::f32::consts::PI;
+
+/// How far away (in meters) a signal can be heard.
+pub const SIGNAL_RANGE: f32 = 6.0;
+/// The signal strength below which a Smitty isn't counted as signalling.
+pub const SIGNAL_MIN_STRENGTH: f32 = 0.05;
+
+/// Plugin that carries the Smittys' signals to their neighbours and shows
+/// them on their sprites.
+pub struct SignalPlugin;
+
+impl Plugin for SignalPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // Hear before each neural update
+            .add_simulation_system(
+                NeuralUpdateStage::Collect,
+                collect_signal_system
+                    .run_in_state(SimulationState::Run)
+                    .run_if(is_neural_update_frame_system),
+            )
+            // Tint the sprites once per rendered frame
+            .add_system_to_stage(CoreStage::PostUpdate, tint_signalling_smittys_system);
+    }
+}
+
+/// What a Smitty hears of the signals around it.
+#[derive(Default, Debug, Copy, Clone)]
+pub struct SignalSense {
+    /// How loud the signals are altogether, from 0.0 (silence) towards 1.0.
+    pub strength: f32,
+    /// The average tone of the signals, weighted by how loud each is, from 0.0
+    /// to 1.0.
+    pub tone: f32,
+    /// The direction the loudest signal is coming from relative to this
+    /// entity's heading, from -1.0 (behind, turning clockwise) to 1.0 (behind,
+    /// turning counter-clockwise).
+    pub bearing: f32,
+}
+
+/// Get how loud a signal of the given strength is at the given distance,
+/// fading to nothing at the edge of the signal range.
+pub fn attenuate(strength: f32, distance: f32) -> f32 {
+    strength * (1.0 - distance / SIGNAL_RANGE).max(0.0)
+}
+
+/// Get the color a Smitty giving off a signal is tinted, from its body color
+/// (silent) to the hue of its tone (at full strength).
+pub fn signal_color(body: Color, strength: f32, tone: f32) -> Color {
+    let strength = strength.clamp(0.0, 1.0);
+    let body = body.as_rgba_f32();
+    let signal = Color::hsl(tone.clamp(0.0, 1.0) * 360.0, 0.9, 0.5).as_rgba_f32();
+    let mix = |i: usize| body[i] + (signal[i] - body[i]) * strength;
+    Color::rgba(mix(0), mix(1), mix(2), body[3])
+}
+
+/// System to let each Smitty hear the signals of the Smittys around it, which
+/// get quieter the farther away they are.
+fn collect_signal_system(
+    simworld: Res<SimWorld>,
+    index: Res<SpatialIndex>,
+    mut query: Query<(Entity, &SimEntityPosRot, &mut SimEntityBrainInputs)>,
+    signallers: Query<&SmittyActions>,
+) {
+    for (entity, pos, mut inputs) in query.iter_mut() {
+        let mut total = 0.0;
+        let mut tone = 0.0;
+        let mut loudest: Option<(f32, Vec2)> = None;
+        for hit in index.within_radius(&simworld, pos.0, SIGNAL_RANGE) {
+            if hit.entity == entity {
+                continue;
+            }
+            let actions = match signallers.get(hit.entity) {
+                Ok(actions) => actions,
+                Err(_) => continue,
+            };
+            let heard = attenuate(actions.signal, hit.distance);
+            if heard <= 0.0 {
+                continue;
+            }
+            total += heard;
+            tone += heard * actions.signal_tone;
+            if loudest.map_or(true, |(loudest, _)| heard > loudest) {
+                loudest = Some((heard, simworld.delta(pos.0, hit.pos)));
+            }
+        }
+
+        inputs.signal = match loudest {
+            Some((_, delta)) => {
+                // Wrap the relative angle between -PI and PI
+                let bearing = (delta.y.atan2(delta.x) - pos.1 + PI).rem_euclid(2.0 * PI) - PI;
+                SignalSense {
+                    strength: total.tanh(),
+                    tone: tone / total,
+                    bearing: bearing / PI,
+                }
+            }
+            None => default(),
+        };
+    }
+}
+
+/// System to tint each Smitty's sprite with the signal it's giving off.
+fn tint_signalling_smittys_system(
+    mut query: Query<(&SmittyActions, &SimEntityTraits, &mut Sprite), Changed<SmittyActions>>,
+) {
+    for (actions, traits, mut sprite) in query.iter_mut() {
+        sprite.color = signal_color(traits.color(), actions.signal, actions.signal_tone);
+    }
+}
@@ -1,18 +1,98 @@
-use crate::ecs::NeuralUpdateStage;
-use bevy::{prelude::*, sprite::Anchor};
+use crate::{
+    config::SimConfig,
+    ecs::{FrameUpdateStage, SimulationAppExt, SimulationState, FRAME_DELTA_SECONDS},
+    season::Season,
+};
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+    sprite::Anchor,
+};
+use iyes_loopless::prelude::*;
 use noise::{NoiseFn, OpenSimplex};
+use std::ops::{Deref, DerefMut};
 
 /// The width and height of the world in meter-wide tiles.
 pub const WORLD_SIZE: (usize, usize) = (25, 25);
 pub const MAX_FOOD: f32 = 1.0;
+/// The maximum amount of meat that can pile up on a tile.
+pub const MAX_MEAT: f32 = 1.0;
+/// The portion of a tile's meat that rots away each second.
+pub const MEAT_DECAY_RATE: f32 = 0.05;
+/// The amount of meat below which a carcass has rotted away completely.
+const MIN_MEAT: f32 = 0.001;
+/// The portion of a tile's max food that grows back each second.
+pub const FOOD_REGROWTH_RATE: f32 = 0.01;
+/// The color tiles are tinted towards as meat piles up on them.
+const MEAT_COLOR: Color = Color::rgb(0.6, 0.05, 0.05);
+/// How far inside the far edges of a walled world positions are clamped so
+/// they still fall within the last tile.
+const BOUNDARY_EPSILON: f32 = 0.0001;
+/// The thickness of the border drawn around worlds that aren't toroidal.
+const BORDER_THICKNESS: f32 = 0.25;
+
+/// The speed multiplier on water for a Smitty that can't swim at all.
+pub const WATER_MIN_SPEED_MULT: f32 = 0.25;
+/// The speed multiplier on land for a Smitty that swims perfectly.
+pub const LAND_MIN_SPEED_MULT: f32 = 0.75;
+/// The energy per second drained from a Smitty that can't swim at all while
+/// it's in the water.
+pub const WATER_ENERGY_DRAIN: f32 = 0.02;
+
+/// How the edges of the simulation world behave.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WorldBoundary {
+    /// Leaving one edge of the world brings you back on the opposite edge.
+    Torus,
+    /// The edges of the world are walls that stop anything moving into them.
+    Walled,
+    /// Anything that moves past the edge of the world dies.
+    Deadly,
+}
+
+impl Default for WorldBoundary {
+    fn default() -> Self {
+        Self::Torus
+    }
+}
 
 /// The types of tiles.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum SimTileType {
     /// A land tile.
     Land,
     /// A water tile.
     Water,
+    /// A rock tile, which nothing can move onto.
+    Rock,
+}
+
+impl SimTileType {
+    /// Returns the multiplier applied to the speed of a Smitty with the given
+    /// swim ability (from 0.0 to 1.0) while it is on this type of tile, or
+    /// `None` if the tile is impassable.
+    pub fn move_speed_mult(&self, swim_ability: f32) -> Option<f32> {
+        let swim = swim_ability.clamp(0.0, 1.0);
+        match self {
+            // Good swimmers are a little clumsy on land
+            Self::Land => Some(1.0 + (LAND_MIN_SPEED_MULT - 1.0) * swim),
+            Self::Water => Some(WATER_MIN_SPEED_MULT + (1.0 - WATER_MIN_SPEED_MULT) * swim),
+            Self::Rock => None,
+        }
+    }
+
+    /// Returns the energy per second drained from a Smitty with the given swim
+    /// ability (from 0.0 to 1.0) while it is on this type of tile, on top of
+    /// its usual metabolism.
+    pub fn energy_drain(&self, swim_ability: f32) -> f32 {
+        match self {
+            Self::Water => WATER_ENERGY_DRAIN * (1.0 - swim_ability.clamp(0.0, 1.0)),
+            Self::Land | Self::Rock => 0.0,
+        }
+    }
 }
 
 impl Default for SimTileType {
@@ -22,7 +102,7 @@ impl Default for SimTileType {
 }
 
 /// A single tile in the simulation world.
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
 pub struct SimTile {
     /// The type of this tile.
     pub tile_type: SimTileType,
@@ -30,6 +110,10 @@ pub struct SimTile {
     pub food: f32,
     /// The maximum amount of food this tile can have.
     pub max_food: f32,
+    /// The amount of meat left by carcasses currently on the tile.
+    pub meat: f32,
+    /// The world frame on which something last grazed on this tile.
+    pub grazed_frame: u32,
 }
 
 impl SimTile {
@@ -38,21 +122,38 @@ impl SimTile {
         match self.tile_type {
             SimTileType::Land => 136.0,
             SimTileType::Water => 202.0,
+            SimTileType::Rock => 30.0,
         }
     }
 
     /// Returns the saturation of this tile from 0.0 to 1.0.
     pub fn sat(&self) -> f32 {
-        0.8
+        match self.tile_type {
+            SimTileType::Rock => 0.1,
+            _ => 0.8,
+        }
     }
 
     /// The lightness (color-wise) of this tile from 0.0 to 1.0.
     pub fn light(&self) -> f32 {
-        (self.food / MAX_FOOD).max(0.0).min(1.0)
+        match self.tile_type {
+            SimTileType::Rock => 0.35,
+            _ => (self.food / MAX_FOOD).max(0.0).min(1.0),
+        }
     }
 
     pub fn color(&self) -> Color {
-        Color::hsl(self.hue(), self.sat(), self.light())
+        let [r, g, b, a] = Color::hsl(self.hue(), self.sat(), self.light()).as_rgba_f32();
+
+        // Tint the tile with the meat on it
+        let meat = (self.meat / MAX_MEAT).clamp(0.0, 1.0);
+        let [mr, mg, mb, _] = MEAT_COLOR.as_rgba_f32();
+        Color::rgba(
+            r + (mr - r) * meat,
+            g + (mg - g) * meat,
+            b + (mb - b) * meat,
+            a,
+        )
     }
 }
 
@@ -60,21 +161,216 @@ impl SimTile {
 #[derive(Resource)]
 pub struct SimWorld {
     tiles: Vec<SimTile>,
-    tile_entities: Vec<Entity>,
     size: (usize, usize),
+    boundary: WorldBoundary,
+    dirty_sets: Vec<DirtyTileSet>,
+}
+
+/// Handle to one consumer's set of the tiles changed since it last took them.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DirtyTileTracker(usize);
+
+/// Mutable access to one of a world's tiles, which marks the tile as changed
+/// when dropped if it was.
+pub struct TileMut<'a> {
+    world: &'a mut SimWorld,
+    index: usize,
+    /// The tile as it was before it was accessed.
+    before: SimTile,
+}
+
+impl Deref for TileMut<'_> {
+    type Target = SimTile;
+
+    fn deref(&self) -> &SimTile {
+        &self.world.tiles[self.index]
+    }
+}
+
+impl DerefMut for TileMut<'_> {
+    fn deref_mut(&mut self) -> &mut SimTile {
+        &mut self.world.tiles[self.index]
+    }
+}
+
+impl Drop for TileMut<'_> {
+    fn drop(&mut self) {
+        if self.world.tiles[self.index] != self.before {
+            self.world.mark_dirty(self.index);
+        }
+    }
+}
+
+/// The tiles changed since a consumer last took them.
+struct DirtyTileSet {
+    /// Whether each tile is already in the list.
+    flags: Vec<bool>,
+    /// The indices of the changed tiles, in the order they changed.
+    tiles: Vec<usize>,
 }
 
 impl SimWorld {
     /// Instantiate an empty world resource.
     pub fn new(size: (usize, usize)) -> Self {
         let s = size.0 * size.1;
+        Self::from_tiles(size, vec![default(); s])
+    }
+
+    /// Instantiate a world with the given tiles, in rows from the bottom of the
+    /// world to the top.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of tiles doesn't match the size of the world.
+    pub fn from_tiles(size: (usize, usize), tiles: Vec<SimTile>) -> Self {
+        assert_eq!(tiles.len(), size.0 * size.1, "wrong number of tiles");
         Self {
-            tiles: vec![default(); s],
-            tile_entities: vec![Entity::from_raw(0); s],
+            tiles,
             size,
+            boundary: default(),
+            dirty_sets: Vec::new(),
+        }
+    }
+
+    /// Replace this world's tiles, size and boundary with another world's,
+    /// keeping the dirty tile trackers and marking every tile as changed.
+    pub fn replace_with(&mut self, other: SimWorld) {
+        self.tiles = other.tiles;
+        self.size = other.size;
+        self.boundary = other.boundary;
+        let s = self.tiles.len();
+        for set in self.dirty_sets.iter_mut() {
+            set.flags = vec![true; s];
+            set.tiles = (0..s).collect();
+        }
+    }
+
+    /// Get how the edges of this simulation world behave.
+    pub fn boundary(&self) -> WorldBoundary {
+        self.boundary
+    }
+
+    /// Change how the edges of this simulation world behave.
+    pub fn set_boundary(&mut self, boundary: WorldBoundary) {
+        self.boundary = boundary;
+    }
+
+    /// Bring a world position that may have left the world back within its
+    /// bounds according to the world's boundary, or `None` if the position is
+    /// outside of a world with deadly edges.
+    ///
+    /// The returned position is always in the half-open range `[0, size)`.
+    pub fn bound_pos(&self, pos: Vec2) -> Option<Vec2> {
+        let (w, h) = (self.size.0 as f32, self.size.1 as f32);
+        match self.boundary {
+            WorldBoundary::Torus => {
+                // `rem_euclid` may round tiny negative values up to the size
+                let wrap = |v: f32, max: f32| {
+                    let v = v.rem_euclid(max);
+                    if v >= max {
+                        0.0
+                    } else {
+                        v
+                    }
+                };
+                Some(Vec2::new(wrap(pos.x, w), wrap(pos.y, h)))
+            }
+            WorldBoundary::Walled => Some(Vec2::new(
+                pos.x.clamp(0.0, w - BOUNDARY_EPSILON),
+                pos.y.clamp(0.0, h - BOUNDARY_EPSILON),
+            )),
+            WorldBoundary::Deadly => {
+                if pos.x >= 0.0 && pos.y >= 0.0 && pos.x < w && pos.y < h {
+                    Some(pos)
+                } else {
+                    None
+                }
+            }
         }
     }
 
+    /// Get the shortest vector from one world position to another, which may
+    /// cross the edges of a toroidal world.
+    pub fn delta(&self, from: Vec2, to: Vec2) -> Vec2 {
+        let mut d = to - from;
+        if self.boundary == WorldBoundary::Torus {
+            let (w, h) = (self.size.0 as f32, self.size.1 as f32);
+            if d.x > w * 0.5 {
+                d.x -= w;
+            } else if d.x < -w * 0.5 {
+                d.x += w;
+            }
+            if d.y > h * 0.5 {
+                d.y -= h;
+            } else if d.y < -h * 0.5 {
+                d.y += h;
+            }
+        }
+        d
+    }
+
+    /// Get the shortest distance between two world positions according to the
+    /// world's boundary.
+    pub fn distance(&self, a: Vec2, b: Vec2) -> f32 {
+        self.delta(a, b).length()
+    }
+
+    /// Get the tiles a ray from the given position crosses within the given
+    /// range, in order, along with the distance at which the ray enters each.
+    /// The ray wraps around the edges of a toroidal world and stops at the
+    /// edges of any other.
+    pub fn ray_tiles(&self, origin: Vec2, dir: Vec2, range: f32) -> Vec<((usize, usize), f32)> {
+        let mut tiles = Vec::new();
+        let dir = dir.normalize_or_zero();
+        if dir == Vec2::ZERO {
+            return tiles;
+        }
+
+        // Walk the grid one tile edge at a time, tracking the distance along
+        // the ray to the next vertical and horizontal edges
+        let step = |d: f32| match d {
+            d if d > 0.0 => 1,
+            d if d < 0.0 => -1,
+            _ => 0,
+        };
+        let step = IVec2::new(step(dir.x), step(dir.y));
+        let mut cell = origin.floor().as_ivec2();
+        let tile_dist = Vec2::new(1.0 / dir.x.abs(), 1.0 / dir.y.abs());
+        let first_edge = |origin: f32, cell: i32, step: i32, tile_dist: f32| match step {
+            1 => (cell as f32 + 1.0 - origin) * tile_dist,
+            -1 => (origin - cell as f32) * tile_dist,
+            _ => f32::INFINITY,
+        };
+        let mut next_edge = Vec2::new(
+            first_edge(origin.x, cell.x, step.x, tile_dist.x),
+            first_edge(origin.y, cell.y, step.y, tile_dist.y),
+        );
+
+        let (w, h) = (self.size.0 as i32, self.size.1 as i32);
+        let mut distance = 0.0;
+        while distance <= range {
+            let tile = match self.boundary {
+                WorldBoundary::Torus => IVec2::new(cell.x.rem_euclid(w), cell.y.rem_euclid(h)),
+                _ => cell,
+            };
+            if tile.x < 0 || tile.y < 0 || tile.x >= w || tile.y >= h {
+                break;
+            }
+            tiles.push(((tile.x as usize, tile.y as usize), distance));
+
+            if next_edge.x < next_edge.y {
+                distance = next_edge.x;
+                next_edge.x += tile_dist.x;
+                cell.x += step.x;
+            } else {
+                distance = next_edge.y;
+                next_edge.y += tile_dist.y;
+                cell.y += step.y;
+            }
+        }
+        tiles
+    }
+
     /// Get the width and height of this simulation world.
     pub fn size(&self) -> (usize, usize) {
         self.size
@@ -89,31 +385,103 @@ impl SimWorld {
         }
     }
 
-    /// Get a mutable reference to the tile at the given position, or `None` if
-    /// out of world bounds.
-    pub fn tile_mut(&mut self, pos: (usize, usize)) -> Option<&mut SimTile> {
-        if pos.0 < self.size.0 && pos.1 < self.size.1 {
-            let i = self.index(pos);
-            Some(&mut self.tiles[i])
+    /// Get the tile containing the given world position, or `None` if out of
+    /// world bounds.
+    pub fn tile_at(&self, pos: Vec2) -> Option<SimTile> {
+        self.tile(self.tile_pos_at(pos)?)
+    }
+
+    /// Get the position of the tile containing the given world position, or
+    /// `None` if out of world bounds.
+    pub fn tile_pos_at(&self, pos: Vec2) -> Option<(usize, usize)> {
+        if pos.x >= 0.0 && pos.y >= 0.0 {
+            let tile_pos = (pos.x as usize, pos.y as usize);
+            (tile_pos.0 < self.size.0 && tile_pos.1 < self.size.1).then_some(tile_pos)
         } else {
             None
         }
     }
 
-    /// Get the ID of the entity representing the rendered tile in the
-    /// simulation world at the given position, or `None` if out of bounds.
-    pub fn tile_entity(&self, pos: (usize, usize)) -> Option<Entity> {
+    /// Get whether something with the given swim ability (from 0.0 to 1.0) can
+    /// stand at the given world position, which it can't outside of the world.
+    pub fn is_passable(&self, pos: Vec2, swim_ability: f32) -> bool {
+        self.tile_at(pos)
+            .and_then(|tile| tile.tile_type.move_speed_mult(swim_ability))
+            .is_some()
+    }
+
+    /// Get whether something with the given swim ability can step from one
+    /// world position to another. Anything stuck on a tile it can't stand on,
+    /// such as one painted over with rock, may step anywhere to get off of it.
+    pub fn can_step(&self, from: Vec2, to: Vec2, swim_ability: f32) -> bool {
+        self.is_passable(to, swim_ability) || !self.is_passable(from, swim_ability)
+    }
+
+    /// Iterate over all the tiles in the world.
+    pub fn tiles(&self) -> impl Iterator<Item = &SimTile> {
+        self.tiles.iter()
+    }
+
+    /// Get mutable access to the tile at the given position, or `None` if out
+    /// of world bounds.
+    ///
+    /// Once the access is dropped, the tile is marked as changed for every
+    /// dirty tile tracker if it was actually changed.
+    pub fn tile_mut(&mut self, pos: (usize, usize)) -> Option<TileMut<'_>> {
         if pos.0 < self.size.0 && pos.1 < self.size.1 {
-            Some(self.tile_entities[self.index(pos)])
+            let index = self.index(pos);
+            Some(TileMut {
+                before: self.tiles[index],
+                world: self,
+                index,
+            })
         } else {
             None
         }
     }
 
+    /// Get mutable access to the tile containing the given world position, or
+    /// `None` if out of world bounds.
+    pub fn tile_at_mut(&mut self, pos: Vec2) -> Option<TileMut<'_>> {
+        self.tile_mut(self.tile_pos_at(pos)?)
+    }
+
+    /// Start tracking which tiles change for a new consumer, starting with
+    /// every tile marked as changed. Worlds nobody tracks don't pay for it.
+    pub fn track_dirty_tiles(&mut self) -> DirtyTileTracker {
+        let s = self.tiles.len();
+        self.dirty_sets.push(DirtyTileSet {
+            flags: vec![true; s],
+            tiles: (0..s).collect(),
+        });
+        DirtyTileTracker(self.dirty_sets.len() - 1)
+    }
+
+    /// Take the positions of the tiles changed since the consumer last took
+    /// them, leaving its set empty.
+    pub fn take_dirty_tiles(&mut self, tracker: DirtyTileTracker) -> Vec<(usize, usize)> {
+        let w = self.size.0;
+        let set = &mut self.dirty_sets[tracker.0];
+        for &i in set.tiles.iter() {
+            set.flags[i] = false;
+        }
+        set.tiles.drain(..).map(|i| (i % w, i / w)).collect()
+    }
+
+    /// Mark the tile at the given index as changed for every tracker.
+    fn mark_dirty(&mut self, i: usize) {
+        for set in self.dirty_sets.iter_mut() {
+            if !set.flags[i] {
+                set.flags[i] = true;
+                set.tiles.push(i);
+            }
+        }
+    }
+
     /// Get the index in the tile vector of the tile at the given position.
     /// This function does not ensure the position is within bounds!
     fn index(&self, pos: (usize, usize)) -> usize {
-        pos.1 * self.size.1 + pos.0
+        pos.1 * self.size.0 + pos.0
     }
 }
 
@@ -129,40 +497,67 @@ pub struct SimWorldPlugin;
 impl Plugin for SimWorldPlugin {
     fn build(&self, app: &mut App) {
         app
-            // Add the world resource
-            .insert_resource(SimWorld::default())
+            // Add the world resource, unless a map was already loaded
+            .init_resource::<SimWorld>()
             // Initialization system
             .add_startup_system(init_simworld_system)
             .add_startup_system(init_generate_world)
             // Update world stage
-            .add_system_to_stage(NeuralUpdateStage::Perform, update_tile_color);
+            .add_simulation_system(
+                FrameUpdateStage::UpdateEntities,
+                update_food_system.run_in_state(SimulationState::Run),
+            )
+            .add_system(update_world_border);
     }
 }
 
+/// Marker for the sprite showing the whole world's tiles as a texture, with
+/// one texel per tile.
 #[derive(Component)]
-pub struct TileMarker;
+pub struct WorldTextureMarker;
+
+/// Marker for the sprites drawn along the edges of the world to show its
+/// boundary, with which edge it's on (left, right, bottom, top).
+#[derive(Component)]
+pub struct WorldBorderMarker(usize);
 
 /// System to initialize the simulation world.
 fn init_simworld_system(
     mut cmds: Commands,
-    mut simworld: ResMut<SimWorld>,
-    assets: Res<AssetServer>,
+    simworld: Res<SimWorld>,
+    images: Option<ResMut<Assets<Image>>>,
 ) {
-    let tile_tex = assets.load("tile.png");
+    // There are no images to draw into when running headless
+    let texture = images.map_or_else(default, |mut images| {
+        images.add(world_texture_image(simworld.size))
+    });
 
     // Spawn the world parent object with its bare necessities
     cmds.spawn((TransformBundle::default(), VisibilityBundle::default()))
         .add_children(|cmds| {
-            // Add each tile sprite into the world.
-            for y in 0..simworld.size.1 {
-                for x in 0..simworld.size.0 {
-                    // Spawn the individual tile sprite
-                    let i = simworld.index((x, y));
-                    simworld.tile_entities[i] = cmds
-                        .spawn(tile_sprite_bundle((x, y), tile_tex.clone()))
-                        .insert(TileMarker)
-                        .id();
-                }
+            // Add the sprite showing every tile
+            cmds.spawn(SpriteBundle {
+                sprite: Sprite {
+                    anchor: Anchor::BottomLeft,
+                    custom_size: Some(Vec2::new(simworld.size.0 as f32, simworld.size.1 as f32)),
+                    ..default()
+                },
+                texture,
+                ..default()
+            })
+            .insert(WorldTextureMarker);
+
+            // Add the border sprites along each edge, which are placed once
+            // the world's size is known
+            for side in 0..4 {
+                cmds.spawn(SpriteBundle {
+                    sprite: Sprite {
+                        anchor: Anchor::BottomLeft,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(WorldBorderMarker(side));
             }
         });
 }
@@ -198,47 +593,317 @@ impl NoiseWrap {
 }
 
 /// System to generate the world.
-fn init_generate_world(mut simworld: ResMut<SimWorld>) {
-    let noise_type = NoiseWrap::new(0, 10.0, None);
-    let noise_max_food = NoiseWrap::new(133780085, 5.0, Some((0.0, 1.0)));
+fn init_generate_world(mut simworld: ResMut<SimWorld>, config: Res<SimConfig>) {
+    // Loaded maps are already generated
+    if config.map.is_some() {
+        return;
+    }
+
+    let noise_type = NoiseWrap::new(config.seed, 10.0, None);
+    let noise_max_food = NoiseWrap::new(config.seed.wrapping_add(133780085), 5.0, Some((0.0, 1.0)));
+    let noise_rock = NoiseWrap::new(config.seed.wrapping_add(8675309), 3.0, None);
 
     for y in 0..simworld.size.1 {
         for x in 0..simworld.size.0 {
             let mut tile = simworld.tile_mut((x, y)).unwrap();
-            tile.tile_type = if noise_type.get(x, y) < 0.0 {
+            tile.tile_type = if noise_rock.get(x, y) > 0.6 {
+                SimTileType::Rock
+            } else if noise_type.get(x, y) < 0.0 {
                 SimTileType::Land
             } else {
                 SimTileType::Water
             };
-            tile.max_food = noise_max_food.get(x, y) * MAX_FOOD;
+            // Nothing grows on rocks
+            tile.max_food = match tile.tile_type {
+                SimTileType::Rock => 0.0,
+                _ => noise_max_food.get(x, y) * MAX_FOOD,
+            };
             tile.food = tile.max_food;
         }
     }
 }
 
-/// System to update tiles' color to their potentially updated color.
-fn update_tile_color(simworld: Res<SimWorld>, mut entities: Query<&mut Sprite, With<TileMarker>>) {
-    for y in 0..simworld.size.1 {
-        for x in 0..simworld.size.0 {
-            let mut sprite = entities
-                .get_mut(simworld.tile_entity((x, y)).unwrap())
-                .unwrap();
-            sprite.color = simworld.tile((x, y)).unwrap().color();
+/// System to grow plant food back (faster in the summer) and rot away meat.
+pub fn update_food_system(mut simworld: ResMut<SimWorld>, season: Res<Season>) {
+    let dt = FRAME_DELTA_SECONDS;
+    let regrowth = FOOD_REGROWTH_RATE * season.food_growth_mult();
+    for i in 0..simworld.tiles.len() {
+        let tile = simworld.tiles[i];
+        let food = (tile.food + tile.max_food * regrowth * dt).min(tile.max_food);
+        let meat = match tile.meat - tile.meat * MEAT_DECAY_RATE * dt {
+            meat if meat < MIN_MEAT => 0.0,
+            meat => meat,
+        };
+
+        // Only touch the tiles that actually changed, so they stay clean
+        if food != tile.food || meat != tile.meat {
+            simworld.tiles[i].food = food;
+            simworld.tiles[i].meat = meat;
+            simworld.mark_dirty(i);
         }
     }
 }
 
-/// Create a sprite bundle for usage as a tile.
-fn tile_sprite_bundle(pos: (usize, usize), texture: Handle<Image>) -> SpriteBundle {
-    SpriteBundle {
-        sprite: Sprite {
-            anchor: Anchor::BottomLeft,
-            custom_size: Some(Vec2::new(1.0, 1.0)),
-            ..default()
+/// System to show the world's boundary mode with the border sprites, which
+/// are hidden for toroidal worlds since they have no edges.
+fn update_world_border(
+    simworld: Res<SimWorld>,
+    mut borders: Query<(
+        &WorldBorderMarker,
+        &mut Sprite,
+        &mut Transform,
+        &mut Visibility,
+    )>,
+) {
+    // The position and size of the border along each edge (left, right,
+    // bottom, top)
+    let (w, h) = (simworld.size.0 as f32, simworld.size.1 as f32);
+    let t = BORDER_THICKNESS;
+    let rects = [
+        (-t, -t, t, h + 2.0 * t),
+        (w, -t, t, h + 2.0 * t),
+        (0.0, -t, w, t),
+        (0.0, h, w, t),
+    ];
+
+    for (border, mut sprite, mut transform, mut visibility) in borders.iter_mut() {
+        let (x, y, bw, bh) = rects[border.0];
+        transform.translation = Vec3::new(x, y, 0.5);
+        sprite.custom_size = Some(Vec2::new(bw, bh));
+        visibility.is_visible = simworld.boundary != WorldBoundary::Torus;
+        sprite.color = match simworld.boundary {
+            WorldBoundary::Deadly => Color::hsl(0.0, 0.8, 0.5),
+            _ => Color::GRAY,
+        };
+    }
+}
+
+/// Create an image to draw the world's tiles into, with one texel per tile.
+pub fn world_texture_image(size: (usize, usize)) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size.0 as u32,
+            height: size.1 as u32,
+            depth_or_array_layers: 1,
         },
-        transform: Transform::from_xyz(pos.0 as f32 + 0.05, pos.1 as f32 + 0.05, 0.5)
-            .with_scale(Vec3::splat(0.9)),
-        texture,
-        ..default()
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    // Keep the tiles crisp when zoomed in
+    image.sampler_descriptor = ImageSampler::nearest();
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a 3x2 world by hand, with the bottom row land, water and rock and
+    /// the top row all land.
+    fn small_world() -> SimWorld {
+        let tile = |tile_type| SimTile {
+            tile_type,
+            ..default()
+        };
+        SimWorld::from_tiles(
+            (3, 2),
+            vec![
+                tile(SimTileType::Land),
+                tile(SimTileType::Water),
+                tile(SimTileType::Rock),
+                tile(SimTileType::Land),
+                tile(SimTileType::Land),
+                tile(SimTileType::Land),
+            ],
+        )
+    }
+
+    #[test]
+    fn move_speed_mult_by_tile_type() {
+        assert_eq!(SimTileType::Land.move_speed_mult(0.0), Some(1.0));
+        assert_eq!(
+            SimTileType::Land.move_speed_mult(1.0),
+            Some(LAND_MIN_SPEED_MULT)
+        );
+        assert_eq!(
+            SimTileType::Water.move_speed_mult(0.0),
+            Some(WATER_MIN_SPEED_MULT)
+        );
+        assert_eq!(SimTileType::Water.move_speed_mult(1.0), Some(1.0));
+    }
+
+    #[test]
+    fn rock_is_impassable() {
+        assert_eq!(SimTileType::Rock.move_speed_mult(0.0), None);
+        assert_eq!(SimTileType::Rock.move_speed_mult(1.0), None);
+    }
+
+    #[test]
+    fn energy_drain_by_tile_type() {
+        assert_eq!(SimTileType::Land.energy_drain(0.0), 0.0);
+        assert_eq!(SimTileType::Land.energy_drain(1.0), 0.0);
+        assert_eq!(SimTileType::Water.energy_drain(0.0), WATER_ENERGY_DRAIN);
+        assert_eq!(SimTileType::Water.energy_drain(1.0), 0.0);
+        assert_eq!(SimTileType::Rock.energy_drain(0.0), 0.0);
+        assert_eq!(SimTileType::Rock.energy_drain(1.0), 0.0);
+    }
+
+    #[test]
+    fn tile_at_edges() {
+        let world = small_world();
+        let tile_type = |x, y| world.tile_at(Vec2::new(x, y)).map(|tile| tile.tile_type);
+        assert_eq!(tile_type(0.0, 0.0), Some(SimTileType::Land));
+        assert_eq!(tile_type(1.5, 0.5), Some(SimTileType::Water));
+        assert_eq!(tile_type(2.999, 0.999), Some(SimTileType::Rock));
+        assert_eq!(tile_type(2.999, 1.999), Some(SimTileType::Land));
+    }
+
+    #[test]
+    fn tile_at_out_of_bounds() {
+        let world = small_world();
+        assert!(world.tile_at(Vec2::new(-0.001, 0.0)).is_none());
+        assert!(world.tile_at(Vec2::new(0.0, -0.001)).is_none());
+        assert!(world.tile_at(Vec2::new(3.0, 0.0)).is_none());
+        assert!(world.tile_at(Vec2::new(0.0, 2.0)).is_none());
+        assert!(world.tile_at(Vec2::new(f32::NAN, 0.0)).is_none());
+    }
+
+    #[test]
+    fn can_step_onto_passable_tiles() {
+        let world = small_world();
+        let (land, water, rock) = (
+            Vec2::new(0.5, 0.5),
+            Vec2::new(1.5, 0.5),
+            Vec2::new(2.5, 0.5),
+        );
+        assert!(world.can_step(land, water, 0.0));
+        assert!(world.can_step(water, land, 0.5));
+        assert!(!world.can_step(land, rock, 0.5));
+        assert!(!world.can_step(land, Vec2::new(-0.5, 0.5), 0.5));
+    }
+
+    #[test]
+    fn can_step_off_impassable_tiles() {
+        let world = small_world();
+        let rock = Vec2::new(2.5, 0.5);
+        assert!(world.can_step(rock, Vec2::new(2.5, 1.5), 0.5));
+        assert!(world.can_step(rock, Vec2::new(2.6, 0.5), 0.5));
+    }
+
+    #[test]
+    fn tile_pos_at_matches_tile_at() {
+        let mut world = small_world();
+        assert_eq!(world.tile_pos_at(Vec2::new(2.999, 1.999)), Some((2, 1)));
+        assert_eq!(world.tile_pos_at(Vec2::new(3.0, 0.0)), None);
+        assert_eq!(world.tile_pos_at(Vec2::new(0.5, -0.5)), None);
+        world.tile_at_mut(Vec2::new(1.5, 0.5)).unwrap().meat = 0.5;
+        assert_eq!(world.tile((1, 0)).unwrap().meat, 0.5);
+        assert!(world.tile_at_mut(Vec2::new(0.5, 2.5)).is_none());
+    }
+
+    #[test]
+    fn ray_tiles_in_order_within_range() {
+        let mut world = small_world();
+        world.set_boundary(WorldBoundary::Walled);
+        assert_eq!(
+            world.ray_tiles(Vec2::new(0.5, 0.5), Vec2::X, 2.0),
+            [((0, 0), 0.0), ((1, 0), 0.5), ((2, 0), 1.5)]
+        );
+        assert_eq!(
+            world.ray_tiles(Vec2::new(0.5, 0.5), Vec2::X, 1.0),
+            [((0, 0), 0.0), ((1, 0), 0.5)]
+        );
+        assert!(world
+            .ray_tiles(Vec2::new(0.5, 0.5), Vec2::ZERO, 2.0)
+            .is_empty());
+    }
+
+    #[test]
+    fn ray_tiles_stop_at_walls() {
+        let mut world = small_world();
+        world.set_boundary(WorldBoundary::Walled);
+        assert_eq!(
+            world.ray_tiles(Vec2::new(2.5, 0.5), Vec2::X, 2.0),
+            [((2, 0), 0.0)]
+        );
+        assert_eq!(
+            world.ray_tiles(Vec2::new(0.5, 0.5), Vec2::NEG_Y, 2.0),
+            [((0, 0), 0.0)]
+        );
+    }
+
+    #[test]
+    fn ray_tiles_across_wrap() {
+        let world = small_world();
+        assert_eq!(world.boundary(), WorldBoundary::Torus);
+        assert_eq!(
+            world.ray_tiles(Vec2::new(2.5, 0.5), Vec2::X, 2.0),
+            [((2, 0), 0.0), ((0, 0), 0.5), ((1, 0), 1.5)]
+        );
+        assert_eq!(
+            world.ray_tiles(Vec2::new(0.5, 0.5), Vec2::NEG_Y, 1.0),
+            [((0, 0), 0.0), ((0, 1), 0.5)]
+        );
+        // Rays longer than the world come back around to where they started
+        assert_eq!(
+            world.ray_tiles(Vec2::new(0.5, 0.5), Vec2::Y, 2.5),
+            [((0, 0), 0.0), ((0, 1), 0.5), ((0, 0), 1.5), ((0, 1), 2.5)]
+        );
+    }
+
+    #[test]
+    fn new_trackers_start_with_every_tile_dirty() {
+        let mut world = small_world();
+        let tracker = world.track_dirty_tiles();
+        let mut dirty = world.take_dirty_tiles(tracker);
+        dirty.sort_unstable();
+        assert_eq!(dirty, [(0, 0), (0, 1), (1, 0), (1, 1), (2, 0), (2, 1)]);
+        assert!(world.take_dirty_tiles(tracker).is_empty());
+    }
+
+    #[test]
+    fn only_changed_tiles_are_dirty() {
+        let mut world = small_world();
+        let tracker = world.track_dirty_tiles();
+        world.take_dirty_tiles(tracker);
+
+        // Looking at a tile, or writing the same value back, changes nothing
+        let food = world.tile_mut((1, 1)).unwrap().food;
+        world.tile_mut((1, 1)).unwrap().food = food;
+        assert!(world.take_dirty_tiles(tracker).is_empty());
+
+        // Each changed tile is listed once, in the order it changed
+        world.tile_mut((2, 1)).unwrap().food = 0.5;
+        world.tile_mut((0, 1)).unwrap().meat = 0.5;
+        world.tile_mut((2, 1)).unwrap().food = 0.25;
+        assert_eq!(world.take_dirty_tiles(tracker), [(2, 1), (0, 1)]);
+        assert!(world.take_dirty_tiles(tracker).is_empty());
+    }
+
+    #[test]
+    fn trackers_are_independent() {
+        let mut world = small_world();
+        let first = world.track_dirty_tiles();
+        let second = world.track_dirty_tiles();
+        world.take_dirty_tiles(first);
+        world.take_dirty_tiles(second);
+
+        world.tile_mut((0, 0)).unwrap().tile_type = SimTileType::Water;
+        assert_eq!(world.take_dirty_tiles(first), [(0, 0)]);
+        world.tile_mut((1, 0)).unwrap().tile_type = SimTileType::Land;
+        assert_eq!(world.take_dirty_tiles(first), [(1, 0)]);
+        assert_eq!(world.take_dirty_tiles(second), [(0, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn replacing_the_world_marks_every_tile_dirty() {
+        let mut world = small_world();
+        let tracker = world.track_dirty_tiles();
+        world.take_dirty_tiles(tracker);
+        world.replace_with(SimWorld::new((2, 2)));
+        assert_eq!(world.take_dirty_tiles(tracker).len(), 4);
+        world.tile_mut((1, 1)).unwrap().food = 1.0;
+        assert_eq!(world.take_dirty_tiles(tracker), [(1, 1)]);
     }
 }
@@ -0,0 +1,333 @@
+//! Spatial index to quickly find the Smittys near a position.
+
+use crate::{
+    ecs::{FrameUpdateStage, SimEntityPosRot, SimulationAppExt, SimulationState},
+    simworld::{SimWorld, WorldBoundary},
+};
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use std::cmp::Ordering;
+
+/// A single entity found by a spatial query.
+#[derive(Debug, Copy, Clone)]
+pub struct SpatialHit {
+    /// The entity that was found.
+    pub entity: Entity,
+    /// The position of the entity when the index was last rebuilt.
+    pub pos: Vec2,
+    /// The distance from the query position to the entity, according to the
+    /// world's boundary.
+    pub distance: f32,
+}
+
+/// Resource containing a uniform grid of the Smittys in the world, with one
+/// cell per tile.
+#[derive(Default, Resource)]
+pub struct SpatialIndex {
+    cells: Vec<Vec<(Entity, Vec2)>>,
+    size: (usize, usize),
+    boundary: WorldBoundary,
+}
+
+impl SpatialIndex {
+    /// Empty the index and fill it with the given entities, sized to match the
+    /// given world.
+    pub fn rebuild(&mut self, simworld: &SimWorld, entities: impl Iterator<Item = (Entity, Vec2)>) {
+        // Resize the grid if the world has changed, otherwise reuse the cells'
+        // allocations
+        let size = simworld.size();
+        if self.size != size {
+            self.cells = vec![Vec::new(); size.0 * size.1];
+            self.size = size;
+        } else {
+            self.cells.iter_mut().for_each(Vec::clear);
+        }
+        self.boundary = simworld.boundary();
+
+        for (entity, pos) in entities {
+            if let Some(i) = self.cell_index(pos.floor().as_ivec2()) {
+                self.cells[i].push((entity, pos));
+            }
+        }
+    }
+
+    /// Find all the entities within the given radius of a position, in no
+    /// particular order.
+    pub fn within_radius(&self, simworld: &SimWorld, pos: Vec2, radius: f32) -> Vec<SpatialHit> {
+        let mut hits = Vec::new();
+        let min = (pos - radius).floor().as_ivec2();
+        let max = (pos + radius).floor().as_ivec2();
+        // Never visit a wrapped cell twice for radii larger than the world
+        let max = max.min(min + IVec2::new(self.size.0 as i32, self.size.1 as i32) - 1);
+
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                self.visit_cell(simworld, pos, IVec2::new(x, y), |hit| {
+                    if hit.distance <= radius {
+                        hits.push(hit);
+                    }
+                });
+            }
+        }
+        hits
+    }
+
+    /// Find up to `k` of the entities nearest to a position, sorted from
+    /// nearest to farthest.
+    pub fn nearest(&self, simworld: &SimWorld, pos: Vec2, k: usize) -> Vec<SpatialHit> {
+        let mut hits = Vec::new();
+        if k == 0 {
+            return hits;
+        }
+
+        // Rings wider than a toroidal world would wrap onto cells they've
+        // already visited, so only visit the cells at most half the world
+        // away each way, which are nearer this way around than the other
+        let (w, h) = (self.size.0 as i32, self.size.1 as i32);
+        let (min, max) = match self.boundary {
+            WorldBoundary::Torus => (
+                IVec2::new(-(w - 1) / 2, -(h - 1) / 2),
+                IVec2::new(w / 2, h / 2),
+            ),
+            _ => (IVec2::splat(i32::MIN), IVec2::splat(i32::MAX)),
+        };
+
+        // Search rings of cells around the position's cell, getting farther
+        // out, until it's certain that no unvisited cell could hold anything
+        // nearer than the `k` entities already found
+        let center = pos.floor().as_ivec2();
+        let max_ring = match self.boundary {
+            WorldBoundary::Torus => w.max(h) / 2,
+            _ => w.max(h),
+        };
+        for ring in 0..=max_ring {
+            for y in (-ring).max(min.y)..=ring.min(max.y) {
+                for x in (-ring).max(min.x)..=ring.min(max.x) {
+                    // Only visit the cells on the edge of the ring
+                    if x.abs() != ring && y.abs() != ring {
+                        continue;
+                    }
+                    self.visit_cell(simworld, pos, center + IVec2::new(x, y), |hit| {
+                        hits.push(hit)
+                    });
+                }
+            }
+
+            // Anything in a farther ring is at least `ring` away
+            let settled = hits
+                .iter()
+                .filter(|hit| hit.distance <= ring as f32)
+                .count();
+            if settled >= k {
+                break;
+            }
+        }
+
+        // Only the nearest `k` need sorting
+        let order = |a: &SpatialHit, b: &SpatialHit| -> Ordering {
+            a.distance
+                .total_cmp(&b.distance)
+                .then(a.entity.cmp(&b.entity))
+        };
+        if hits.len() > k {
+            hits.select_nth_unstable_by(k - 1, order);
+            hits.truncate(k);
+        }
+        hits.sort_unstable_by(order);
+        hits
+    }
+
+    /// Call the given function for each entity in a cell, with the distance to
+    /// it from the given position.
+    fn visit_cell(
+        &self,
+        simworld: &SimWorld,
+        pos: Vec2,
+        cell: IVec2,
+        mut f: impl FnMut(SpatialHit),
+    ) {
+        if let Some(i) = self.cell_index(cell) {
+            for &(entity, entity_pos) in self.cells[i].iter() {
+                f(SpatialHit {
+                    entity,
+                    pos: entity_pos,
+                    distance: simworld.distance(pos, entity_pos),
+                });
+            }
+        }
+    }
+
+    /// Get the index of the given cell, wrapping it around the world if the
+    /// world is toroidal, or `None` if it's outside of the world.
+    fn cell_index(&self, cell: IVec2) -> Option<usize> {
+        let (w, h) = (self.size.0 as i32, self.size.1 as i32);
+        let cell = match self.boundary {
+            WorldBoundary::Torus if w > 0 && h > 0 => {
+                IVec2::new(cell.x.rem_euclid(w), cell.y.rem_euclid(h))
+            }
+            _ => cell,
+        };
+        if cell.x >= 0 && cell.y >= 0 && cell.x < w && cell.y < h {
+            Some(cell.y as usize * self.size.0 + cell.x as usize)
+        } else {
+            None
+        }
+    }
+}
+
+/// Plugin that keeps the spatial index up to date.
+pub struct SpatialIndexPlugin;
+
+impl Plugin for SpatialIndexPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // Add the index resource
+            .init_resource::<SpatialIndex>()
+            // Rebuild it once entities have moved and the dead are gone
+            .add_simulation_system(FrameUpdateStage::UpdateIndex, rebuild_spatial_index_system)
+            // Keep it up to date while stopped too, since no ticks run then
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                rebuild_spatial_index_system.run_not_in_state(SimulationState::Run),
+            );
+    }
+}
+
+/// System to rebuild the spatial index with the current Smitty positions.
+pub fn rebuild_spatial_index_system(
+    simworld: Res<SimWorld>,
+    mut index: ResMut<SpatialIndex>,
+    query: Query<(Entity, &SimEntityPosRot)>,
+) {
+    index.rebuild(&simworld, query.iter().map(|(entity, pos)| (entity, pos.0)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build an index over an empty world of the given size and boundary,
+    /// with entities numbered in the order of their positions.
+    fn index_of(
+        size: (usize, usize),
+        boundary: WorldBoundary,
+        positions: &[Vec2],
+    ) -> (SimWorld, SpatialIndex) {
+        let mut simworld = SimWorld::new(size);
+        simworld.set_boundary(boundary);
+        let mut index = SpatialIndex::default();
+        index.rebuild(
+            &simworld,
+            positions
+                .iter()
+                .enumerate()
+                .map(|(i, &pos)| (Entity::from_raw(i as u32), pos)),
+        );
+        (simworld, index)
+    }
+
+    fn entities(hits: &[SpatialHit]) -> Vec<u32> {
+        hits.iter().map(|hit| hit.entity.index()).collect()
+    }
+
+    #[test]
+    fn within_radius_across_wrap() {
+        let positions = [Vec2::new(0.5, 0.5), Vec2::new(5.0, 5.0)];
+        let (simworld, index) = index_of((10, 10), WorldBoundary::Torus, &positions);
+        let hits = index.within_radius(&simworld, Vec2::new(9.5, 9.5), 1.5);
+        assert_eq!(entities(&hits), [0]);
+        assert!((hits[0].distance - 2f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn within_radius_stops_at_walls() {
+        let positions = [Vec2::new(0.5, 0.5)];
+        let (simworld, index) = index_of((10, 10), WorldBoundary::Walled, &positions);
+        assert!(index
+            .within_radius(&simworld, Vec2::new(9.5, 9.5), 1.5)
+            .is_empty());
+    }
+
+    #[test]
+    fn within_radius_larger_than_world() {
+        let positions = [Vec2::new(0.5, 0.5), Vec2::new(2.5, 1.5)];
+        let (simworld, index) = index_of((3, 2), WorldBoundary::Torus, &positions);
+        let mut found = entities(&index.within_radius(&simworld, Vec2::ZERO, 100.0));
+        found.sort_unstable();
+        assert_eq!(found, [0, 1]);
+    }
+
+    #[test]
+    fn nearest_across_wrap() {
+        let positions = [
+            Vec2::new(0.5, 0.5),
+            Vec2::new(9.5, 0.5),
+            Vec2::new(4.5, 4.5),
+        ];
+        let (simworld, index) = index_of((10, 10), WorldBoundary::Torus, &positions);
+        let hits = index.nearest(&simworld, Vec2::new(0.5, 0.5), 2);
+        assert_eq!(entities(&hits), [0, 1]);
+        assert!((hits[1].distance - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn nearest_on_narrow_torus_finds_k() {
+        // Rings taller than the world wrap onto cells already visited
+        let positions = [Vec2::new(0.5, 0.5), Vec2::new(10.5, 0.5)];
+        let (simworld, index) = index_of((20, 2), WorldBoundary::Torus, &positions);
+        let hits = index.nearest(&simworld, Vec2::new(0.5, 0.5), 2);
+        assert_eq!(entities(&hits), [0, 1]);
+    }
+
+    #[test]
+    fn nearest_finds_fewer_than_k_when_there_are_fewer() {
+        let positions = [Vec2::new(1.5, 1.5), Vec2::new(3.5, 1.5)];
+        let (simworld, index) = index_of((5, 3), WorldBoundary::Walled, &positions);
+        let hits = index.nearest(&simworld, Vec2::new(0.5, 0.5), 5);
+        assert_eq!(entities(&hits), [0, 1]);
+    }
+
+    #[test]
+    fn nearest_on_tiny_torus_finds_each_once() {
+        let positions = [Vec2::new(0.5, 0.5), Vec2::new(2.5, 1.5)];
+        for size in [(3, 2), (4, 4), (1, 1)] {
+            let positions =
+                positions.map(|pos| pos.min(Vec2::new(size.0 as f32, size.1 as f32) - 0.5));
+            let (simworld, index) = index_of(size, WorldBoundary::Torus, &positions);
+            let hits = index.nearest(&simworld, Vec2::new(0.5, 0.5), 5);
+            assert_eq!(entities(&hits), [0, 1], "on a {:?} torus", size);
+        }
+    }
+
+    #[test]
+    fn nearest_matches_checking_everything() {
+        // Scatter entities over odd and even sized worlds, and check the
+        // nearest are the same as found by measuring the distance to each
+        let positions = (0..40)
+            .map(|i| Vec2::new((i * 37 % 61) as f32 / 8.5, (i * 53 % 47) as f32 / 9.5))
+            .collect::<Vec<_>>();
+        for boundary in [WorldBoundary::Torus, WorldBoundary::Walled] {
+            for size in [(8, 5), (7, 6)] {
+                let positions = positions
+                    .iter()
+                    .map(|pos| pos.min(Vec2::new(size.0 as f32, size.1 as f32) - 0.01))
+                    .collect::<Vec<_>>();
+                let (simworld, index) = index_of(size, boundary, &positions);
+                for query in [
+                    Vec2::new(0.2, 0.3),
+                    Vec2::new(3.5, 2.5),
+                    Vec2::new(6.9, 4.9),
+                ] {
+                    let mut expected = (0..positions.len() as u32).collect::<Vec<_>>();
+                    expected.sort_by(|&a, &b| {
+                        let distance = |i: u32| simworld.distance(query, positions[i as usize]);
+                        distance(a).total_cmp(&distance(b)).then(a.cmp(&b))
+                    });
+                    expected.truncate(6);
+                    let hits = index.nearest(&simworld, query, 6);
+                    assert_eq!(entities(&hits), expected, "{:?} {:?}", boundary, size);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Population statistics, recorded over time.
+
+use crate::{
+    ecs::{
+        is_neural_update_frame_system, FrameUpdateStage, SimEntityEnergy, SimEntityLineage,
+        SimEntityPosRot, SimEntitySpecies, SimEntityTraits, SimTime, SimulationAppExt,
+        SimulationState, SmittyActions, SmittyDeathEvent,
+    },
+    signal::{SIGNAL_MIN_STRENGTH, SIGNAL_RANGE},
+    simworld::SimWorld,
+    spatial::{rebuild_spatial_index_system, SpatialIndex},
+    vision::kinship,
+};
+use bevy::{prelude::*, utils::HashSet};
+use iyes_loopless::prelude::*;
+use std::collections::VecDeque;
+
+/// The number of samples kept before the oldest are forgotten.
+pub const STATS_HISTORY_LEN: usize = 4096;
+
+/// The statistics of the population at a single neural frame.
+#[derive(Default, Debug, Copy, Clone)]
+pub struct StatsSample {
+    /// The neural frame the sample was taken on.
+    pub neural_frame: u32,
+    /// The world frame the sample was taken on.
+    pub world_frame: u32,
+    /// The number of living Smittys.
+    pub population: u32,
+    /// The number of Smittys born since the last sample, not counting the
+    /// founders.
+    pub births: u32,
+    /// The number of Smittys that died since the last sample.
+    pub deaths: u32,
+    /// The mean energy of the living Smittys.
+    pub energy_mean: f32,
+    /// The lowest energy of any living Smitty.
+    pub energy_min: f32,
+    /// The highest energy of any living Smitty.
+    pub energy_max: f32,
+    /// The total food on all of the tiles in the world.
+    pub total_food: f32,
+    /// The mean of each trait of the living Smittys.
+    pub mean_traits: SimEntityTraits,
+    /// The number of species with living members.
+    pub species: u32,
+    /// The mean strength of the signals the living Smittys give off.
+    pub signal_mean: f32,
+    /// The number of living Smittys giving off a signal.
+    pub signallers: u32,
+    /// How strongly the Smittys' signal strength correlates with how closely
+    /// related the Smittys within earshot are, from -1.0 (signalling around
+    /// strangers) to 1.0 (signalling around kin), or 0.0 if it can't be told.
+    pub signal_kinship_correlation: f32,
+}
+
+/// Resource containing a ring buffer of the most recent statistics samples.
+#[derive(Resource)]
+pub struct SimStats {
+    samples: VecDeque<StatsSample>,
+    /// The births counted since the last sample.
+    pending_births: u32,
+    /// The entities that died since the last sample.
+    pending_deaths: HashSet<Entity>,
+}
+
+impl Default for SimStats {
+    fn default() -> Self {
+        Self {
+            samples: VecDeque::with_capacity(STATS_HISTORY_LEN),
+            pending_births: 0,
+            pending_deaths: default(),
+        }
+    }
+}
+
+impl SimStats {
+    /// Iterate over the recorded samples, from oldest to newest.
+    pub fn samples(&self) -> impl Iterator<Item = &StatsSample> + '_ {
+        self.samples.iter()
+    }
+
+    /// Get the newest sample, if one has been recorded.
+    pub fn latest(&self) -> Option<&StatsSample> {
+        self.samples.back()
+    }
+
+    /// Add a sample, forgetting the oldest if the buffer is full.
+    fn push(&mut self, sample: StatsSample) {
+        if self.samples.len() >= STATS_HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+}
+
+/// Plugin that records the population statistics while the simulation runs.
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // Add the statistics resource
+            .init_resource::<SimStats>()
+            // Count births and deaths every frame, then sample on neural frames
+            // once the dead are gone
+            .add_simulation_system(
+                FrameUpdateStage::Cleanup,
+                count_births_and_deaths_system.run_in_state(SimulationState::Run),
+            )
+            .add_simulation_system(
+                FrameUpdateStage::UpdateIndex,
+                record_stats_system
+                    .run_in_state(SimulationState::Run)
+                    .run_if(is_neural_update_frame_system)
+                    .after(rebuild_spatial_index_system),
+            );
+    }
+}
+
+/// System to count the Smittys born and killed since the last sample.
+fn count_births_and_deaths_system(
+    mut stats: ResMut<SimStats>,
+    mut deaths: EventReader<SmittyDeathEvent>,
+    born: Query<&SimEntityLineage, Added<SimEntityEnergy>>,
+) {
+    // The founders are placed in the world rather than born into it
+    stats.pending_births += born
+        .iter()
+        .filter(|lineage| lineage.parent.is_some())
+        .count() as u32;
+    // A Smitty may die more than one way in the same frame
+    stats
+        .pending_deaths
+        .extend(deaths.iter().map(|death| death.entity));
+}
+
+/// System to record a sample of the population statistics.
+pub fn record_stats_system(
+    mut stats: ResMut<SimStats>,
+    sim_time: Res<SimTime>,
+    simworld: Res<SimWorld>,
+    index: Res<SpatialIndex>,
+    smittys: Query<(
+        &SimEntityEnergy,
+        &SimEntityTraits,
+        &SimEntitySpecies,
+        &SmittyActions,
+    )>,
+    kin: Query<(
+        Entity,
+        &SimEntityPosRot,
+        &SimEntityTraits,
+        &SimEntitySpecies,
+    )>,
+    signals: Query<&SmittyActions>,
+) {
+    let mut sample = StatsSample {
+        neural_frame: sim_time.neural_frame,
+        world_frame: sim_time.world_frame,
+        births: stats.pending_births,
+        deaths: stats.pending_deaths.len() as u32,
+        total_food: simworld.tiles().map(|tile| tile.food).sum(),
+        energy_min: f32::INFINITY,
+        energy_max: f32::NEG_INFINITY,
+        ..default()
+    };
+    stats.pending_births = 0;
+    stats.pending_deaths.clear();
+
+    // Sum everything up, then divide for the means
+    let mut species = HashSet::new();
+    let traits = &mut sample.mean_traits;
+    for (energy, smitty_traits, smitty_species, actions) in smittys.iter() {
+        sample.population += 1;
+        sample.signal_mean += actions.signal;
+        if actions.signal >= SIGNAL_MIN_STRENGTH {
+            sample.signallers += 1;
+        }
+        sample.energy_mean += energy.0;
+        sample.energy_min = sample.energy_min.min(energy.0);
+        sample.energy_max = sample.energy_max.max(energy.0);
+        traits.max_move_speed += smitty_traits.max_move_speed;
+        traits.max_rot_speed += smitty_traits.max_rot_speed;
+        traits.swim_ability += smitty_traits.swim_ability;
+        traits.size += smitty_traits.size;
+        traits.density += smitty_traits.density;
+        traits.armour += smitty_traits.armour;
+        traits.diet += smitty_traits.diet;
+        traits.vision_rays += smitty_traits.vision_ray_count() as f32;
+        traits.vision_range += smitty_traits.vision_range;
+        traits.vision_fov += smitty_traits.vision_fov;
+        traits.lifespan += smitty_traits.lifespan;
+        species.insert(*smitty_species);
+    }
+    sample.species = species.len() as u32;
+
+    if sample.population > 0 {
+        let n = sample.population as f32;
+        sample.energy_mean /= n;
+        sample.signal_mean /= n;
+        traits.max_move_speed /= n;
+        traits.max_rot_speed /= n;
+        traits.swim_ability /= n;
+        traits.size /= n;
+        traits.density /= n;
+        traits.armour /= n;
+        traits.diet /= n;
+        traits.vision_rays /= n;
+        traits.vision_range /= n;
+        traits.vision_fov /= n;
+        traits.lifespan /= n;
+    } else {
+        sample.energy_min = 0.0;
+        sample.energy_max = 0.0;
+    }
+
+    sample.signal_kinship_correlation =
+        signal_kinship_correlation(&simworld, &index, &kin, &signals);
+
+    stats.push(sample);
+}
+
+/// Get the correlation between how strongly each Smitty signals and the mean
+/// kinship of the Smittys within earshot of it, over the Smittys that have
+/// anyone to hear them.
+fn signal_kinship_correlation(
+    simworld: &SimWorld,
+    index: &SpatialIndex,
+    kin: &Query<(
+        Entity,
+        &SimEntityPosRot,
+        &SimEntityTraits,
+        &SimEntitySpecies,
+    )>,
+    signals: &Query<&SmittyActions>,
+) -> f32 {
+    let pairs = kin
+        .iter()
+        .filter_map(|(entity, pos, traits, species)| {
+            let (total, count) = index
+                .within_radius(simworld, pos.0, SIGNAL_RANGE)
+                .into_iter()
+                .filter(|hit| hit.entity != entity)
+                .filter_map(|hit| kin.get(hit.entity).ok())
+                .fold(
+                    (0.0, 0),
+                    |(total, count), (_, _, other_traits, other_species)| {
+                        (
+                            total + kinship(traits, *species, other_traits, *other_species),
+                            count + 1,
+                        )
+                    },
+                );
+            let signal = signals.get(entity).ok()?.signal;
+            (count > 0).then(|| (signal, total / count as f32))
+        })
+        .collect::<Vec<_>>();
+    if pairs.len() < 2 {
+        return 0.0;
+    }
+
+    // Pearson's correlation coefficient
+    let n = pairs.len() as f32;
+    let (mean_x, mean_y) = pairs
+        .iter()
+        .fold((0.0, 0.0), |(x, y), &(px, py)| (x + px / n, y + py / n));
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for &(x, y) in pairs.iter() {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x) * (x - mean_x);
+        var_y += (y - mean_y) * (y - mean_y);
+    }
+    if var_x <= f32::EPSILON || var_y <= f32::EPSILON {
+        0.0
+    } else {
+        cov / (var_x * var_y).sqrt()
+    }
+}
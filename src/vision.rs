@@ -0,0 +1,198 @@
+//! Letting Smittys see their surroundings with a cone of rays cast across the
+//! world.
+
+use crate::{
+    ecs::{
+        is_neural_update_frame_system, NeuralUpdateStage, SimEntityBrainInputs, SimEntityPosRot,
+        SimEntitySpecies, SimEntityTraits, SimulationAppExt, SimulationState, SMITTY_MAX_RADIUS,
+    },
+    simworld::{SimTileType, SimWorld},
+    spatial::SpatialIndex,
+};
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+/// The most vision rays a Smitty may have, which is how many the brain has
+/// inputs for.
+pub const MAX_VISION_RAYS: usize = 5;
+/// The farthest (in meters) a Smitty's vision rays may reach.
+pub const SMITTY_MAX_VISION_RANGE: f32 = 10.0;
+/// The shortest (in meters) a Smitty's vision rays may reach.
+pub const SMITTY_MIN_VISION_RANGE: f32 = 1.0;
+/// The widest (in radians) a Smitty's field of view may be.
+pub const SMITTY_MAX_VISION_FOV: f32 = 1.5 * std::f32::consts::PI;
+/// The narrowest (in radians) a Smitty's field of view may be.
+pub const SMITTY_MIN_VISION_FOV: f32 = 0.1;
+/// The energy per second spent on each vision ray at the maximum range and
+/// field of view.
+pub const SMITTY_VISION_RAY_DRAIN: f32 = 0.001;
+
+/// Plugin that fills in the Smittys' vision inputs before each neural update.
+pub struct VisionPlugin;
+
+impl Plugin for VisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_simulation_system(
+            NeuralUpdateStage::Collect,
+            collect_vision_system
+                .run_in_state(SimulationState::Run)
+                .run_if(is_neural_update_frame_system),
+        );
+    }
+}
+
+/// What a single vision ray saw.
+#[derive(Default, Debug, Copy, Clone)]
+pub struct VisionRay {
+    /// How close the thing the ray hit is, from 0.0 (nothing in range) to 1.0
+    /// (right next to the Smitty).
+    pub proximity: f32,
+    /// 1.0 if the ray hit water, otherwise 0.0.
+    pub water: f32,
+    /// The average fraction of the maximum food on the tiles the ray crossed
+    /// before hitting anything.
+    pub food: f32,
+    /// 1.0 if the ray hit another Smitty, otherwise 0.0.
+    pub smitty: f32,
+    /// How closely related the Smitty the ray hit is, from 0.0 (not at all, or
+    /// no Smitty) to 1.0 (the same species).
+    pub kinship: f32,
+}
+
+impl VisionRay {
+    /// The number of brain inputs for each ray.
+    pub const COUNT: usize = 5;
+    /// The names of each ray's inputs, in the order they're fed into the brain.
+    pub const NAMES: [&'static str; Self::COUNT] =
+        ["proximity", "water", "food", "Smitty", "kinship"];
+
+    /// Get the ray's inputs in the order they're fed into the brain.
+    pub fn to_array(&self) -> [f32; Self::COUNT] {
+        [
+            self.proximity,
+            self.water,
+            self.food,
+            self.smitty,
+            self.kinship,
+        ]
+    }
+}
+
+/// Get how closely related two Smittys are, from 0.0 to 1.0 (the same
+/// species).
+pub fn kinship(
+    traits: &SimEntityTraits,
+    species: SimEntitySpecies,
+    other_traits: &SimEntityTraits,
+    other_species: SimEntitySpecies,
+) -> f32 {
+    if species == other_species {
+        1.0
+    } else {
+        (1.0 - traits.distance(other_traits)).clamp(0.0, 1.0)
+    }
+}
+
+/// System to cast each Smitty's vision rays and record what they saw.
+///
+/// A ray stops at the first water or rock tile past the one the Smitty is
+/// standing on, or the first Smitty whose body it passes through, whichever
+/// is nearer.
+fn collect_vision_system(
+    simworld: Res<SimWorld>,
+    index: Res<SpatialIndex>,
+    mut query: Query<(
+        Entity,
+        &SimEntityPosRot,
+        &SimEntityTraits,
+        &SimEntitySpecies,
+        &mut SimEntityBrainInputs,
+    )>,
+    others: Query<(&SimEntityTraits, &SimEntitySpecies)>,
+) {
+    for (entity, pos, traits, species, mut inputs) in query.iter_mut() {
+        let range = traits.vision_range;
+        let count = traits.vision_ray_count();
+        // Only look up the nearby Smittys once for all the rays
+        let nearby = index
+            .within_radius(&simworld, pos.0, range + SMITTY_MAX_RADIUS)
+            .into_iter()
+            .filter(|hit| hit.entity != entity)
+            .filter_map(|hit| {
+                let (other_traits, other_species) = others.get(hit.entity).ok()?;
+                let delta = simworld.delta(pos.0, hit.pos);
+                let radius = other_traits.radius();
+                Some((delta, radius, other_traits, *other_species))
+            })
+            .collect::<Vec<_>>();
+
+        for (i, ray) in inputs.vision.iter_mut().enumerate() {
+            if i >= count {
+                *ray = default();
+                continue;
+            }
+
+            // Spread the rays evenly across the field of view
+            let angle = if count > 1 {
+                pos.1 - traits.vision_fov * 0.5 + traits.vision_fov * i as f32 / (count - 1) as f32
+            } else {
+                pos.1
+            };
+            let dir = Vec2::new(angle.cos(), angle.sin());
+
+            // The nearest Smitty the ray passes through
+            let smitty_hit = nearby
+                .iter()
+                .filter_map(|&(delta, radius, other_traits, other_species)| {
+                    let along = delta.dot(dir);
+                    let across = dir.perp_dot(delta).abs();
+                    if across > radius {
+                        return None;
+                    }
+                    // Where the ray enters the Smitty's body
+                    let distance = along - (radius * radius - across * across).sqrt();
+                    (distance >= 0.0 && distance <= range).then(|| {
+                        (
+                            distance,
+                            kinship(traits, *species, other_traits, other_species),
+                        )
+                    })
+                })
+                .min_by(|a, b| a.0.total_cmp(&b.0));
+
+            // Walk the tiles until the ray hits something
+            let mut seen = VisionRay::default();
+            let mut hit_terrain = false;
+            let mut food = (0.0, 0);
+            let max_distance = smitty_hit.map_or(range, |(distance, _)| distance);
+            for (tile_pos, distance) in simworld
+                .ray_tiles(pos.0, dir, max_distance)
+                .into_iter()
+                .skip(1)
+            {
+                let tile = simworld.tile(tile_pos).unwrap();
+                if tile.tile_type != SimTileType::Land {
+                    seen.proximity = 1.0 - distance / range;
+                    seen.water = (tile.tile_type == SimTileType::Water) as u32 as f32;
+                    hit_terrain = true;
+                    break;
+                }
+                if tile.max_food > 0.0 {
+                    food.0 += tile.food / tile.max_food;
+                    food.1 += 1;
+                }
+            }
+            if !hit_terrain {
+                if let Some((distance, kinship)) = smitty_hit {
+                    seen.proximity = 1.0 - distance / range;
+                    seen.smitty = 1.0;
+                    seen.kinship = kinship;
+                }
+            }
+            if food.1 > 0 {
+                seen.food = food.0 / food.1 as f32;
+            }
+            *ray = seen;
+        }
+    }
+}